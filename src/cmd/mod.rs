@@ -1,4 +1,5 @@
 use crate::common::*;
+use crate::solver::{Histories, NodeType, Tree};
 use std::fmt::Display;
 use std::io;
 use rand::Rng;
@@ -54,4 +55,69 @@ pub fn rng_random<T: Clone>(p: &Vec<f64>, _: &Vec<T>) -> usize {
         }
     }
     panic!("distribution should sum to 1")
-}
\ No newline at end of file
+}
+
+fn print_node<G: Game>(node: &Tree<G>, hist: &Histories, path: &[String]) {
+    println!("Path: {}", if path.is_empty() { "(root)".to_string() } else { path.join(" / ") });
+    match node.node_type() {
+        NodeType::Message1(m) => println!("Message to P1:\n{m}"),
+        NodeType::Message2(m) => println!("Message to P2:\n{m}"),
+        NodeType::Player1(c) => {
+            println!("P1 choice:");
+            print_vec_with_indices(c);
+        }
+        NodeType::Player2(c) => {
+            println!("P2 choice:");
+            print_vec_with_indices(c);
+        }
+        NodeType::Random(r) => {
+            println!("Random:");
+            print_vec_with_indices(r);
+        }
+        NodeType::End => println!("End"),
+    }
+    println!("Value: {:?}, Prob: {:?}", node.value(), node.prob());
+    if let Some(info_set) = hist.info_set(node.node_type()) {
+        println!("Information set: {info_set}");
+    }
+}
+
+pub fn browse_tree<G: Game>(tree: &Tree<G>) {
+    let mut stack = vec![(tree, Histories::default())];
+    let mut path: Vec<String> = Vec::new();
+    loop {
+        let (node, hist) = stack.last().expect("browser stack should never be empty");
+        print_node(node, hist, &path);
+        println!("Enter a child index, u (up), r (root) or q (quit):");
+        let mut buf = String::new();
+        if io::stdin().read_line(&mut buf).expect("STDIN should be readable") == 0 {
+            return;
+        }
+        match buf.trim() {
+            "q" => return,
+            "u" => {
+                if stack.len() > 1 {
+                    stack.pop();
+                    path.pop();
+                }
+            }
+            "r" => {
+                stack.truncate(1);
+                path.clear();
+            }
+            cmd => match cmd.parse::<usize>() {
+                Ok(i) if i < node.num_children() => match node.child(i) {
+                    Some(child) => {
+                        let child_hist = hist.child(node.node_type(), i);
+                        let label = node.node_type().child_label(i);
+                        path.push(if label.is_empty() { i.to_string() } else { label });
+                        stack.push((child, child_hist));
+                    }
+                    None => println!("Child {i} is not expanded"),
+                },
+                Ok(i) => println!("Invalid child {i}"),
+                Err(err) => println!("Invalid command {err}"),
+            },
+        }
+    }
+}
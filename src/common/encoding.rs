@@ -0,0 +1,183 @@
+use crate::common::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodingError {
+    UnknownKind { kind: usize, kinds: usize },
+    WrongLength { kind: usize, expected: usize, found: usize },
+}
+
+impl Display for EncodingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingError::UnknownKind { kind, kinds } => write!(f, "kind {kind} is out of range, kind_sizes declares {kinds} kinds"),
+            EncodingError::WrongLength { kind, expected, found } => write!(f, "kind {kind} should serialize to {expected} values, found {found}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+// Checks a serialized value against the sizes its type declares
+pub fn validate<T: Serializable>(t: &T, sizes: &[usize]) -> Result<(usize, Vec<i32>), EncodingError> {
+    let (kind, payload) = t.serialize();
+    match sizes.get(kind) {
+        None => Err(EncodingError::UnknownKind { kind, kinds: sizes.len() }),
+        Some(&expected) if expected != payload.len() => Err(EncodingError::WrongLength { kind, expected, found: payload.len() }),
+        Some(_) => Ok((kind, payload)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    KeepFirst,
+    KeepLast,
+}
+
+// Fixed-width encodings of a Serializable type. The payload layout gives every kind its own slot, in kind order;
+// a token is a one-hot kind tag followed by the payload layout with only its kind's slot filled
+#[derive(Clone, Debug)]
+pub struct Encoder<T: Serializable> {
+    sizes: Vec<usize>,
+    offsets: Vec<usize>,
+    item_type: PhantomData<T>,
+}
+
+impl<T: Serializable> Default for Encoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Serializable> Encoder<T> {
+    pub fn new() -> Encoder<T> {
+        let sizes = T::kind_sizes();
+        let offsets = sizes.iter().scan(0, |acc, s| {
+            let offset = *acc;
+            *acc += s;
+            Some(offset)
+        }).collect();
+        Encoder { sizes, offsets, item_type: PhantomData }
+    }
+
+    pub fn kinds(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn payload_width(&self) -> usize {
+        self.sizes.iter().sum()
+    }
+
+    pub fn token_width(&self) -> usize {
+        self.kinds() + self.payload_width()
+    }
+
+    // out should be token_width long and zeroed
+    pub fn encode_into(&self, t: &T, out: &mut [f32]) -> Result<(), EncodingError> {
+        let (kind, payload) = validate(t, &self.sizes)?;
+        out[kind] = 1.;
+        let start = self.kinds() + self.offsets[kind];
+        for (o, p) in out[start..].iter_mut().zip(payload) {
+            *o = p as f32;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self, t: &T) -> Result<Vec<f32>, EncodingError> {
+        let mut ret = vec_of_repeat(self.token_width(), 0.);
+        self.encode_into(t, &mut ret)?;
+        Ok(ret)
+    }
+
+    // Exactly max_len tokens, padded at the end with all-zero tokens, which have no kind tag set. Longer sequences
+    // keep their first or last max_len items
+    pub fn encode_sequence(&self, seq: &[T], max_len: usize, truncation: Truncation) -> Result<Vec<f32>, EncodingError> {
+        let kept = match truncation {
+            Truncation::KeepFirst => &seq[..seq.len().min(max_len)],
+            Truncation::KeepLast => &seq[seq.len().saturating_sub(max_len)..],
+        };
+        let width = self.token_width();
+        let mut ret = vec_of_repeat(max_len * width, 0.);
+        for (t, out) in kept.iter().zip(ret.chunks_mut(width)) {
+            self.encode_into(t, out)?;
+        }
+        Ok(ret)
+    }
+
+    // Adds the payload into its kind's slot of out, which should be payload_width long
+    pub fn add_to_bag(&self, t: &T, out: &mut [f32]) -> Result<(), EncodingError> {
+        let (kind, payload) = validate(t, &self.sizes)?;
+        for (o, p) in out[self.offsets[kind]..].iter_mut().zip(payload) {
+            *o += p as f32;
+        }
+        Ok(())
+    }
+
+    // Sum of the payloads, ignoring order, so sequences of any length share one width
+    pub fn bag<'a>(&self, seq: impl IntoIterator<Item = &'a T>) -> Result<Vec<f32>, EncodingError> where T: 'a {
+        let mut ret = vec_of_repeat(self.payload_width(), 0.);
+        for t in seq {
+            self.add_to_bag(t, &mut ret)?;
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A card with a rank, or a bet with an amount and whether it is all in
+    #[derive(Clone, Debug)]
+    enum Token {
+        Card(i32),
+        Bet(i32, bool),
+        // Serializes to a kind kind_sizes does not declare
+        Unknown,
+        // Serializes to the wrong length for a bet
+        Short,
+    }
+
+    impl Display for Token {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl Serializable for Token {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1, 2]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            match *self {
+                Token::Card(rank) => (0, vec![rank]),
+                Token::Bet(amount, all_in) => (1, vec![amount, all_in as i32]),
+                Token::Unknown => (2, vec![]),
+                Token::Short => (1, vec![5]),
+            }
+        }
+    }
+
+    #[test]
+    fn tokens_tag_their_kind_and_fill_its_slot() {
+        let encoder = Encoder::<Token>::new();
+        assert_eq!((2, 3, 5), (encoder.kinds(), encoder.payload_width(), encoder.token_width()));
+        assert_eq!(vec![1., 0., 7., 0., 0.], encoder.encode(&Token::Card(7)).unwrap());
+        assert_eq!(vec![0., 1., 0., 4., 1.], encoder.encode(&Token::Bet(4, true)).unwrap());
+        assert_eq!(Err(EncodingError::UnknownKind { kind: 2, kinds: 2 }), encoder.encode(&Token::Unknown));
+        assert_eq!(Err(EncodingError::WrongLength { kind: 1, expected: 2, found: 1 }), encoder.encode(&Token::Short));
+    }
+
+    #[test]
+    fn sequences_pad_and_truncate() {
+        let encoder = Encoder::<Token>::new();
+        let seq = [Token::Card(1), Token::Bet(2, false), Token::Card(3)];
+        let card = |rank| vec![1., 0., rank, 0., 0.];
+        let padded = encoder.encode_sequence(&seq[..1], 2, Truncation::KeepFirst).unwrap();
+        assert_eq!([card(1.), vec![0.; 5]].concat(), padded);
+        let first = encoder.encode_sequence(&seq, 2, Truncation::KeepFirst).unwrap();
+        assert_eq!([card(1.), vec![0., 1., 0., 2., 0.]].concat(), first);
+        let last = encoder.encode_sequence(&seq, 1, Truncation::KeepLast).unwrap();
+        assert_eq!(card(3.), last);
+        assert_eq!(vec![4., 2., 0.], encoder.bag(&seq).unwrap());
+    }
+}
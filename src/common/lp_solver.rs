@@ -1,60 +1,362 @@
 use crate::common::*;
-use good_lp::{self, coin_cbc, variable, Solution, SolverModel};
+use good_lp::{self, coin_cbc, constraint::ConstraintReference, variable, Solution, SolverModel};
+use std::io::{self, Write};
+use std::marker::PhantomData;
 
 pub trait Solver {
     // todo: tie the variable to the solver instance
     type Variable: Clone;
+    type Constraint: Clone;
     fn new() -> Self;
     fn new_var(&mut self) -> Self::Variable;
-    fn add_constraint(&mut self, v: &Vec<(f64, Self::Variable)>, o: Ordering, c: f64);
+    fn add_constraint(&mut self, v: &Vec<(f64, Self::Variable)>, o: Ordering, c: f64) -> Self::Constraint;
+    fn num_vars(&self) -> usize;
+    fn num_constraints(&self) -> usize;
+    // Drops every constraint added after the first num_constraints
+    fn truncate(&mut self, num_constraints: usize);
+    // Constraints added so far survive solve_consuming
+    fn keep(&mut self);
     fn solve(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> impl Fn(Self::Variable) -> f64;
+    // Like solve, but constraints added after the last keep() are handed to the backend and dropped as it goes, so
+    // the LP is never held twice. They have to be added again to solve again
+    fn solve_consuming(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> impl Fn(Self::Variable) -> f64;
 }
 
-pub struct DefaultSolver {
+// Solvers that also report the dual value (shadow price) of every constraint
+pub trait DualSolver: Solver {
+    fn solve_with_dual(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> (impl Fn(Self::Variable) -> f64, impl Fn(Self::Constraint) -> f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModelStats {
+    pub variables: usize,
+    pub constraints: usize,
+    pub nonzeros: usize,
+}
+
+impl Display for ModelStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} variables, {} constraints, {} nonzeros", self.variables, self.constraints, self.nonzeros)
+    }
+}
+
+// Constraints are kept in compressed sparse rows, and only turned into the backend's model while solving
+pub struct SparseModel {
     next_var: usize,
-    constraints: Vec<(Vec<(f64, usize)>, Ordering, f64)>,
+    coeffs: Vec<f64>,
+    vars: Vec<u32>,
+    row_starts: Vec<usize>,
+    rows: Vec<(Ordering, f64)>,
+    // Rows before this survive a consuming build
+    kept: usize,
+}
+
+impl SparseModel {
+    fn row(&self, r: usize) -> impl ExactSizeIterator<Item = (f64, usize)> + '_ {
+        let end = self.row_starts.get(r + 1).cloned().unwrap_or(self.coeffs.len());
+        (self.row_starts[r]..end).map(|i| (self.coeffs[i], self.vars[i] as usize))
+    }
+
+    fn add_row<M: SolverModel>(&self, lp: &mut M, vars: &[good_lp::Variable], r: usize) -> ConstraintReference {
+        let mut expr = good_lp::Expression::with_capacity(self.row(r).len());
+        for (c, v) in self.row(r) {
+            expr.add_mul(c, vars[v]);
+        }
+        let (ord, constant) = self.rows[r];
+        lp.add_constraint(match ord {
+            Ordering::Equal => expr.eq(constant),
+            Ordering::Greater => expr.geq(constant),
+            Ordering::Less => expr.leq(constant),
+        })
+    }
+
+    // Streams the rows into the backend. When consuming, rows after the kept ones are popped off the end as they go,
+    // and their storage released, so the backend's copy replaces them instead of doubling them
+    fn build<B: good_lp::Solver>(&mut self, maximize_coeffs: &[(f64, usize)], backend: B, consume: bool) -> (B::Model, Vec<good_lp::Variable>, Vec<ConstraintReference>) {
+        let mut problem = good_lp::variables!();
+        let vars = problem.add_vector(variable(), self.next_var);
+        let mut goal = good_lp::Expression::with_capacity(maximize_coeffs.len());
+        for &(c, v) in maximize_coeffs {
+            goal.add_mul(c, vars[v]);
+        }
+        let mut lp = problem.maximise(goal).using(backend);
+        let kept = if consume { self.kept } else { self.rows.len() };
+        let mut constraints: Vec<ConstraintReference> = (0..kept).map(|r| self.add_row(&mut lp, &vars, r)).collect();
+        let mut dropped = Vec::with_capacity(self.rows.len() - kept);
+        while self.rows.len() > kept {
+            let r = self.rows.len() - 1;
+            dropped.push(self.add_row(&mut lp, &vars, r));
+            self.pop_row();
+        }
+        constraints.extend(dropped.into_iter().rev());
+        (lp, vars, constraints)
+    }
+
+    fn pop_row(&mut self) {
+        let start = self.row_starts.pop().expect("model should have a row to pop");
+        self.coeffs.truncate(start);
+        self.vars.truncate(start);
+        self.rows.pop();
+        if self.coeffs.len() < self.coeffs.capacity()/2 {
+            self.coeffs.shrink_to_fit();
+            self.vars.shrink_to_fit();
+        }
+    }
+
+    pub fn stats(&self) -> ModelStats {
+        ModelStats {
+            variables: self.next_var,
+            constraints: self.rows.len(),
+            nonzeros: self.coeffs.len(),
+        }
+    }
+
+    // CPLEX LP format; every variable is free, as in solve()
+    pub fn write_lp<W: Write>(&self, mut w: W, maximize_coeffs: &[(f64, usize)]) -> io::Result<()> {
+        writeln!(w, "Maximize")?;
+        write!(w, " obj:")?;
+        write_lp_terms(&mut w, maximize_coeffs.iter().cloned())?;
+        writeln!(w, "\nSubject To")?;
+        for r in 0..self.rows.len() {
+            let (ord, constant) = self.rows[r];
+            let ord = match ord {
+                Ordering::Equal => "=",
+                Ordering::Greater => ">=",
+                Ordering::Less => "<=",
+            };
+            // A row without terms constrains no variable, and LP format has no way to write one. That's fine while
+            // it holds, but one that doesn't makes the LP infeasible, which a comment would hide
+            if self.row(r).next().is_none() {
+                if !empty_row_holds(self.rows[r]) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("row c{r}, 0 {ord} {constant}, can't hold")));
+                }
+                writeln!(w, "\\ c{r}: 0 {ord} {constant}")?;
+                continue;
+            }
+            write!(w, " c{r}:")?;
+            write_lp_terms(&mut w, self.row(r))?;
+            writeln!(w, " {ord} {constant}")?;
+        }
+        writeln!(w, "Bounds")?;
+        for v in 0..self.next_var {
+            writeln!(w, " x{v} free")?;
+        }
+        writeln!(w, "End")
+    }
+
+    // Free-format MPS
+    pub fn write_mps<W: Write>(&self, mut w: W, maximize_coeffs: &[(f64, usize)]) -> io::Result<()> {
+        let mut columns: Vec<Vec<(String, f64)>> = vec_of_repeat(self.next_var, Vec::new());
+        for &(c, v) in maximize_coeffs {
+            columns[v].push(("obj".to_string(), c));
+        }
+        writeln!(w, "NAME bluff_tree")?;
+        writeln!(w, "OBJSENSE\n    MAX")?;
+        writeln!(w, "ROWS\n N obj")?;
+        for r in 0..self.rows.len() {
+            writeln!(w, " {} c{r}", match self.rows[r].0 {
+                Ordering::Equal => "E",
+                Ordering::Greater => "G",
+                Ordering::Less => "L",
+            })?;
+            for (c, v) in self.row(r) {
+                columns[v].push((format!("c{r}"), c));
+            }
+        }
+        writeln!(w, "COLUMNS")?;
+        for (v, column) in columns.iter().enumerate() {
+            for (row, c) in column {
+                writeln!(w, "    x{v} {row} {c}")?;
+            }
+        }
+        writeln!(w, "RHS")?;
+        for r in 0..self.rows.len() {
+            if self.rows[r].1 != 0. {
+                writeln!(w, "    RHS c{r} {}", self.rows[r].1)?;
+            }
+        }
+        writeln!(w, "BOUNDS")?;
+        for v in 0..self.next_var {
+            writeln!(w, " FR BND x{v}")?;
+        }
+        writeln!(w, "ENDATA")
+    }
+}
+
+fn empty_row_holds((ord, constant): (Ordering, f64)) -> bool {
+    match ord {
+        Ordering::Equal => constant == 0.,
+        Ordering::Greater => constant <= 0.,
+        Ordering::Less => constant >= 0.,
+    }
+}
+
+fn write_lp_terms<W: Write>(w: &mut W, terms: impl Iterator<Item = (f64, usize)>) -> io::Result<()> {
+    for (c, v) in terms {
+        write!(w, " {} {} x{v}", if c < 0. { "-" } else { "+" }, c.abs())?;
+    }
+    Ok(())
 }
 
-impl Solver for DefaultSolver {
+// The LP backend a SparseSolver hands its model to. When consuming, rows after the kept ones are dropped
+pub trait Backend {
+    fn solve(model: &mut SparseModel, maximize_coeffs: &[(f64, usize)], consume: bool) -> Vec<f64>;
+}
+
+pub trait DualBackend: Backend {
+    fn solve_with_dual(model: &mut SparseModel, maximize_coeffs: &[(f64, usize)]) -> (Vec<f64>, Vec<f64>);
+}
+
+pub struct Cbc;
+
+impl Backend for Cbc {
+    fn solve(model: &mut SparseModel, maximize_coeffs: &[(f64, usize)], consume: bool) -> Vec<f64> {
+        let (lp, vars, _) = model.build(maximize_coeffs, coin_cbc, consume);
+        let solution = lp.solve().expect("lp system should be solvable");
+        vars.iter().map(|&v| solution.value(v)).collect()
+    }
+}
+
+// Interior point solver, the only enabled backend that reports duals
+#[cfg(feature = "clarabel")]
+pub struct Clarabel;
+
+#[cfg(feature = "clarabel")]
+impl Backend for Clarabel {
+    fn solve(model: &mut SparseModel, maximize_coeffs: &[(f64, usize)], consume: bool) -> Vec<f64> {
+        let (lp, vars, _) = model.build(maximize_coeffs, good_lp::clarabel, consume);
+        let solution = lp.solve().expect("lp system should be solvable");
+        vars.iter().map(|&v| solution.value(v)).collect()
+    }
+}
+
+#[cfg(feature = "clarabel")]
+impl DualBackend for Clarabel {
+    fn solve_with_dual(model: &mut SparseModel, maximize_coeffs: &[(f64, usize)]) -> (Vec<f64>, Vec<f64>) {
+        use good_lp::{DualValues, SolutionWithDual};
+        let (lp, vars, constraints) = model.build(maximize_coeffs, good_lp::clarabel, false);
+        let mut solution = lp.solve().expect("lp system should be solvable");
+        let primal = vars.iter().map(|&v| solution.value(v)).collect();
+        let dual = solution.compute_dual();
+        (primal, constraints.into_iter().map(|c| dual.dual(c)).collect())
+    }
+}
+
+pub struct SparseSolver<B> {
+    model: SparseModel,
+    backend: PhantomData<B>,
+}
+
+pub type DefaultSolver = SparseSolver<Cbc>;
+
+impl<B> SparseSolver<B> {
+    pub fn model(&self) -> &SparseModel {
+        &self.model
+    }
+}
+
+impl<B: Backend> Solver for SparseSolver<B> {
     type Variable = usize;
+    type Constraint = usize;
 
     fn new() -> Self {
-        DefaultSolver {
-            next_var: 0,
-            constraints: Vec::new(),
+        SparseSolver {
+            model: SparseModel {
+                next_var: 0,
+                coeffs: Vec::new(),
+                vars: Vec::new(),
+                row_starts: Vec::new(),
+                rows: Vec::new(),
+                kept: 0,
+            },
+            backend: PhantomData,
         }
     }
 
     fn new_var(&mut self) -> Self::Variable {
-        let v = self.next_var;
-        self.next_var += 1;
+        let v = self.model.next_var;
+        self.model.next_var += 1;
         return v;
     }
 
-    fn add_constraint(&mut self, coeffs: &Vec<(f64, Self::Variable)>, ord: Ordering, constant: f64) {
-        self.constraints.push((coeffs.clone(), ord, constant));
+    fn add_constraint(&mut self, coeffs: &Vec<(f64, Self::Variable)>, ord: Ordering, constant: f64) -> Self::Constraint {
+        let m = &mut self.model;
+        m.row_starts.push(m.coeffs.len());
+        for &(c, v) in coeffs.iter().filter(|(c, _)| *c != 0.) {
+            m.coeffs.push(c);
+            m.vars.push(u32::try_from(v).expect("variable index should fit in u32"));
+        }
+        m.rows.push((ord, constant));
+        m.rows.len() - 1
     }
 
-    fn solve(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> impl Fn(Self::Variable) -> f64 {
-        let mut problem = good_lp::variables!();
-        let vars = problem.add_vector(variable(), self.next_var);
-        let mut goal = good_lp::Expression::with_capacity(maximize_coeffs.len());
-        for (c, v) in maximize_coeffs {
-            goal = goal+c*vars[v]
-        }
-        let mut lp = problem.maximise(goal).using(coin_cbc);
-        for (coeffs, ord, constant) in &self.constraints {
-            let mut expr = good_lp::Expression::with_capacity(coeffs.len());
-            for (c, v) in coeffs {
-                expr = expr+*c*vars[*v];
-            }
-            lp.add_constraint(match ord {
-                Ordering::Equal => expr.eq(*constant),
-                Ordering::Greater => expr.geq(*constant),
-                Ordering::Less => expr.leq(*constant),
-            });
+    fn num_vars(&self) -> usize {
+        self.model.next_var
+    }
+
+    fn num_constraints(&self) -> usize {
+        self.model.rows.len()
+    }
+
+    fn truncate(&mut self, num_constraints: usize) {
+        let m = &mut self.model;
+        if num_constraints < m.rows.len() {
+            m.coeffs.truncate(m.row_starts[num_constraints]);
+            m.vars.truncate(m.row_starts[num_constraints]);
+            m.row_starts.truncate(num_constraints);
+            m.rows.truncate(num_constraints);
+            m.kept = m.kept.min(num_constraints);
         }
-        let solution = lp.clone().solve().expect("lp system should be solvable");
-        move |v| solution.value(vars[v])
     }
-}
\ No newline at end of file
+
+    fn keep(&mut self) {
+        self.model.kept = self.model.rows.len();
+    }
+
+    fn solve(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> impl Fn(Self::Variable) -> f64 {
+        let primal = B::solve(&mut self.model, &maximize_coeffs, false);
+        move |v| primal[v]
+    }
+
+    fn solve_consuming(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> impl Fn(Self::Variable) -> f64 {
+        let primal = B::solve(&mut self.model, &maximize_coeffs, true);
+        move |v| primal[v]
+    }
+}
+
+impl<B: DualBackend> DualSolver for SparseSolver<B> {
+    fn solve_with_dual(&mut self, maximize_coeffs: Vec<(f64, Self::Variable)>) -> (impl Fn(Self::Variable) -> f64, impl Fn(Self::Constraint) -> f64) {
+        let (primal, dual) = B::solve_with_dual(&mut self.model, &maximize_coeffs);
+        (move |v| primal[v], move |c| dual[c])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_solve_consuming_drops_unkept_rows() {
+        let mut s = DefaultSolver::new();
+        let x = s.new_var();
+        s.add_constraint(&vec![(1., x)], Ordering::Less, 2.);
+        s.keep();
+        s.add_constraint(&vec![(1., x)], Ordering::Less, 1.);
+        assert!((s.solve(vec![(1., x)])(x) - 1.).abs() < 1e-6);
+        assert!((s.solve(vec![(1., x)])(x) - 1.).abs() < 1e-6);
+        assert!((s.solve_consuming(vec![(1., x)])(x) - 1.).abs() < 1e-6);
+        assert_eq!(1, s.num_constraints());
+        assert!((s.solve(vec![(1., x)])(x) - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn write_lp_rejects_an_empty_row_that_cannot_hold() {
+        let mut s = DefaultSolver::new();
+        let x = s.new_var();
+        s.add_constraint(&vec![(1., x)], Ordering::Less, 1.);
+        s.add_constraint(&vec![(0., x)], Ordering::Greater, -1.);
+        assert!(s.model().write_lp(Vec::new(), &[(1., x)]).is_ok());
+        s.add_constraint(&vec![], Ordering::Equal, 1.);
+        assert!(s.model().write_lp(Vec::new(), &[(1., x)]).is_err());
+    }
+}
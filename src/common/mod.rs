@@ -1,4 +1,6 @@
+pub mod encoding;
 pub mod lp_solver;
+pub mod multi;
 
 pub use std::marker::PhantomData;
 pub use std::fmt::{Display, Debug};
@@ -6,6 +8,8 @@ pub use std::cmp::Ordering;
 pub use std::hash::Hash;
 
 pub const EPS: f64 = 1e-9;
+// serialize returns (kind, payload): kind indexes kind_sizes() and the payload has exactly kind_sizes()[kind]
+// entries, so one type can mix differently shaped values. See encoding for fixed-width encoders built on this
 pub trait Serializable {
     fn kind_sizes() -> Vec<usize>;
     fn serialize(&self) -> (usize, Vec<i32>);
@@ -0,0 +1,134 @@
+use crate::common::*;
+
+// Games with any number of players, numbered from 1, whose payoffs need not sum to zero. All players share one
+// PlayerTraits; ZeroSum makes every two-player Game one of these
+pub trait MultiGame {
+    type Traits: PlayerTraits + Clone + Debug;
+    type RandomChoice: Display + Serializable + Clone + Debug;
+    fn num_players(&self) -> usize;
+    fn step(&mut self, _: &mut dyn MultiGameInterface<Self>) -> Option<()>;
+}
+
+pub trait MultiGameInterface<G: MultiGame> {
+    fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize>;
+    fn choice(&mut self, player: usize, v: &Vec<<G::Traits as PlayerTraits>::Choice>) -> Option<usize>;
+    fn message(&mut self, player: usize, msg: &<G::Traits as PlayerTraits>::Message) -> Option<()>;
+    // One payoff per player, in player order
+    fn end(&mut self, payoffs: &[f64]);
+}
+
+pub struct DefaultMultiGameInterface<G: MultiGame, R: FnMut(&Vec<f64>, &Vec<G::RandomChoice>)->usize, E: FnMut(&[f64])> {
+    pub game_type: PhantomData<G>,
+    pub randomer: R,
+    // players[0] is player 1
+    pub players: Vec<Box<dyn Player<G::Traits>>>,
+    pub ender: E,
+}
+
+impl<G: MultiGame, R: FnMut(&Vec<f64>, &Vec<G::RandomChoice>)->usize, E: FnMut(&[f64])> MultiGameInterface<G> for DefaultMultiGameInterface<G, R, E> {
+    fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize> {
+        Some((self.randomer)(p, v))
+    }
+    fn choice(&mut self, player: usize, v: &Vec<<G::Traits as PlayerTraits>::Choice>) -> Option<usize> {
+        Some(self.players[player - 1].choose(v))
+    }
+    fn message(&mut self, player: usize, msg: &<G::Traits as PlayerTraits>::Message) -> Option<()> {
+        self.players[player - 1].receive_message(msg);
+        Some(())
+    }
+    fn end(&mut self, payoffs: &[f64]) {
+        (self.ender)(payoffs)
+    }
+}
+
+pub fn run_multi_game<G: MultiGame>(game: &mut G, g: &mut dyn MultiGameInterface<G>) {
+    loop {
+        if game.step(g).is_none() {
+            return;
+        }
+    }
+}
+
+// A message or choice of either player of a two-player Game. Kinds of P2's type come after P1's
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Side<A, B> {
+    P1(A),
+    P2(B),
+}
+
+impl<A: Display, B: Display> Display for Side<A, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::P1(a) => a.fmt(f),
+            Side::P2(b) => b.fmt(f),
+        }
+    }
+}
+
+impl<A: Serializable, B: Serializable> Serializable for Side<A, B> {
+    fn kind_sizes() -> Vec<usize> {
+        let mut ret = A::kind_sizes();
+        ret.extend(B::kind_sizes());
+        ret
+    }
+    fn serialize(&self) -> (usize, Vec<i32>) {
+        match self {
+            Side::P1(a) => a.serialize(),
+            Side::P2(b) => {
+                let (kind, payload) = b.serialize();
+                (A::kind_sizes().len() + kind, payload)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SideTraits<P1, P2> {
+    player_types: PhantomData<(P1, P2)>,
+}
+
+impl<P1: PlayerTraits, P2: PlayerTraits> PlayerTraits for SideTraits<P1, P2> {
+    type Message = Side<P1::Message, P2::Message>;
+    type Choice = Side<P1::Choice, P2::Choice>;
+}
+
+// A two-player zero-sum Game as a MultiGame, paying (value, -value)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ZeroSum<G: Game>(pub G);
+
+struct ZeroSumInterface<'a, G: Game> {
+    outer: &'a mut dyn MultiGameInterface<ZeroSum<G>>,
+}
+
+impl<G: Game> GameInterface<G> for ZeroSumInterface<'_, G> {
+    fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize> {
+        self.outer.random(p, v)
+    }
+    fn p1_choice(&mut self, v: &Vec<<G::P1 as PlayerTraits>::Choice>) -> Option<usize> {
+        self.outer.choice(1, &v.iter().cloned().map(Side::P1).collect())
+    }
+    fn p2_choice(&mut self, v: &Vec<<G::P2 as PlayerTraits>::Choice>) -> Option<usize> {
+        self.outer.choice(2, &v.iter().cloned().map(Side::P2).collect())
+    }
+    fn p1_message(&mut self, msg: &<G::P1 as PlayerTraits>::Message) -> Option<()> {
+        self.outer.message(1, &Side::P1(msg.clone()))
+    }
+    fn p2_message(&mut self, msg: &<G::P2 as PlayerTraits>::Message) -> Option<()> {
+        self.outer.message(2, &Side::P2(msg.clone()))
+    }
+    fn end(&mut self, value: f64) {
+        self.outer.end(&[value, -value])
+    }
+}
+
+impl<G: Game> MultiGame for ZeroSum<G> {
+    type Traits = SideTraits<G::P1, G::P2>;
+    type RandomChoice = G::RandomChoice;
+    fn num_players(&self) -> usize {
+        2
+    }
+    fn step(&mut self, g: &mut dyn MultiGameInterface<Self>) -> Option<()> {
+        self.0.step(&mut ZeroSumInterface { outer: g })
+    }
+}
@@ -1,6 +1,7 @@
 use crate::common::*;
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RPS {
     p1_choice: Option<i32>
 }
@@ -14,6 +15,7 @@ impl RPS {
 }
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Choice {
     v: i32,
 }
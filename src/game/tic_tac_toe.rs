@@ -1,12 +1,14 @@
 use crate::common::*;
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TicTacToe {
     board: [[i32; 3]; 3],
     curr_player: i32
 }
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Choice {
     x: usize,
     y: usize,
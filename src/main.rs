@@ -3,8 +3,19 @@ use bluff_tree::common::*;
 use bluff_tree::cmd::*;
 
 fn main() {
+    if std::env::args().any(|a| a == "stats") {
+        println!("{}", bluff_tree::solver::estimate_size(&TicTacToe::new(), 1000, &mut rand::thread_rng()));
+        let mut tree = bluff_tree::solver::Tree::new_root(TicTacToe::new());
+        bluff_tree::solver::expand_full(&mut tree);
+        println!("{}", bluff_tree::solver::tree_stats(&tree));
+        return;
+    }
     let tree = bluff_tree::solver::Tree::new(TicTacToe::new());
-    run_game(&mut bluff_tree::solver::TreeGame::new(tree), &mut DefaultGameInterface{
+    if std::env::args().any(|a| a == "browse") {
+        browse_tree(&tree);
+        return;
+    }
+    run_game(&mut bluff_tree::solver::TreeGame::with_observer(tree, bluff_tree::solver::PrintObserver), &mut DefaultGameInterface{
         game_type: std::marker::PhantomData,
         randomer: rng_random,
         player1: ConsolePlayer{prefix: "P1".to_string()},
@@ -0,0 +1,218 @@
+use super::*;
+use crate::common::encoding::Encoder;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashSet;
+
+type Messages1<G> = Vec<<<G as Game>::P1 as PlayerTraits>::Message>;
+type Messages2<G> = Vec<<<G as Game>::P2 as PlayerTraits>::Message>;
+
+// Maps everything a player has been told so far to a bucket. Information sets with the same earlier choices, bucket
+// and number of choices are solved as one, and each of them plays the shared strategy
+pub trait Abstraction<G: Game> {
+    type Bucket1: Clone + Eq + Hash;
+    type Bucket2: Clone + Eq + Hash;
+    fn bucket1(&self, msgs: &[<G::P1 as PlayerTraits>::Message]) -> Self::Bucket1;
+    fn bucket2(&self, msgs: &[<G::P2 as PlayerTraits>::Message]) -> Self::Bucket2;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoAbstraction;
+
+impl<G: Game> Abstraction<G> for NoAbstraction {
+    type Bucket1 = Messages1<G>;
+    type Bucket2 = Messages2<G>;
+    fn bucket1(&self, msgs: &[<G::P1 as PlayerTraits>::Message]) -> Self::Bucket1 {
+        msgs.to_vec()
+    }
+    fn bucket2(&self, msgs: &[<G::P2 as PlayerTraits>::Message]) -> Self::Bucket2 {
+        msgs.to_vec()
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y)*(x - y)).sum()
+}
+
+fn nearest(centroids: &[Vec<f32>], point: &[f32]) -> usize {
+    centroids.iter().enumerate().fold((0, f32::INFINITY), |best, (i, c)| {
+        let d = distance(c, point);
+        if d < best.1 { (i, d) } else { best }
+    }).0
+}
+
+// Lloyd's algorithm from k distinct points, returning the centroids and each point's cluster. Stops early once no
+// point changes cluster; a cluster that loses all its points keeps its last centroid
+pub fn kmeans<R: Rng>(points: &[Vec<f32>], k: usize, iterations: usize, rng: &mut R) -> (Vec<Vec<f32>>, Vec<usize>) {
+    if points.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    let mut centroids: Vec<Vec<f32>> = points.choose_multiple(rng, k).cloned().collect();
+    let mut assignment: Vec<usize> = points.iter().map(|p| nearest(&centroids, p)).collect();
+    for _ in 0..iterations {
+        let mut sums = vec_of_repeat(centroids.len(), (vec_of_repeat(points[0].len(), 0.), 0));
+        for (p, &a) in points.iter().zip(&assignment) {
+            for (s, x) in sums[a].0.iter_mut().zip(p) {
+                *s += x;
+            }
+            sums[a].1 += 1;
+        }
+        for (c, (sum, count)) in centroids.iter_mut().zip(sums) {
+            if count > 0 {
+                *c = sum.into_iter().map(|s| s/count as f32).collect();
+            }
+        }
+        let next: Vec<usize> = points.iter().map(|p| nearest(&centroids, p)).collect();
+        if next == assignment {
+            break;
+        }
+        assignment = next;
+    }
+    (centroids, assignment)
+}
+
+// Buckets message histories by k-means over their bagged serializations, so message order is ignored. Histories
+// not seen when clustering go to the nearest centroid
+#[derive(Clone, Debug)]
+pub struct KMeansAbstraction<G: Game> {
+    encoder1: Encoder<<G::P1 as PlayerTraits>::Message>,
+    encoder2: Encoder<<G::P2 as PlayerTraits>::Message>,
+    pub centroids: (Vec<Vec<f32>>, Vec<Vec<f32>>),
+}
+
+impl<G: Game> KMeansAbstraction<G> {
+    // Clusters the distinct histories each player has at their decision nodes into up to buckets.0 and buckets.1,
+    // seeding k-means from rng
+    pub fn new<T: GameTree<G>, R: Rng>(tree: &T, buckets: (usize, usize), iterations: usize, rng: &mut R) -> KMeansAbstraction<G> {
+        let (encoder1, encoder2) = (Encoder::new(), Encoder::new());
+        let mut histories = (Distinct::default(), Distinct::default());
+        histories_rec(tree, tree.root(), &Vec::new(), &Vec::new(), &mut histories);
+        let features1: Vec<Vec<f32>> = histories.0.order.iter().map(|h| encoder1.bag(h).expect("serialized messages should match kind_sizes")).collect();
+        let features2: Vec<Vec<f32>> = histories.1.order.iter().map(|h| encoder2.bag(h).expect("serialized messages should match kind_sizes")).collect();
+        let centroids = (
+            kmeans(&features1, buckets.0, iterations, rng).0,
+            kmeans(&features2, buckets.1, iterations, rng).0,
+        );
+        KMeansAbstraction { encoder1, encoder2, centroids }
+    }
+}
+
+impl<G: Game> Abstraction<G> for KMeansAbstraction<G> {
+    type Bucket1 = usize;
+    type Bucket2 = usize;
+    fn bucket1(&self, msgs: &[<G::P1 as PlayerTraits>::Message]) -> usize {
+        nearest(&self.centroids.0, &self.encoder1.bag(msgs).expect("serialized messages should match kind_sizes"))
+    }
+    fn bucket2(&self, msgs: &[<G::P2 as PlayerTraits>::Message]) -> usize {
+        nearest(&self.centroids.1, &self.encoder2.bag(msgs).expect("serialized messages should match kind_sizes"))
+    }
+}
+
+// Histories in the order they were first seen, so k-means starts from the same points for the same rng
+struct Distinct<M> {
+    seen: HashSet<M>,
+    order: Vec<M>,
+}
+
+impl<M> Default for Distinct<M> {
+    fn default() -> Self {
+        Distinct { seen: HashSet::new(), order: Vec::new() }
+    }
+}
+
+impl<M: Clone + Eq + Hash> Distinct<M> {
+    fn insert(&mut self, m: &M) {
+        if self.seen.insert(m.clone()) {
+            self.order.push(m.clone());
+        }
+    }
+}
+
+fn histories_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, msgs1: &Messages1<G>, msgs2: &Messages2<G>, histories: &mut (Distinct<Messages1<G>>, Distinct<Messages2<G>>)) {
+    let node_type = tree.node_type(node);
+    match node_type {
+        NodeType::Player1(_) => {
+            histories.0.insert(msgs1);
+        }
+        NodeType::Player2(_) => {
+            histories.1.insert(msgs2);
+        }
+        _ => {}
+    }
+    for i in 0..tree.num_children(node) {
+        let Some(child) = tree.child(node, i) else { continue };
+        let (mut next1, mut next2) = (msgs1.clone(), msgs2.clone());
+        match node_type {
+            NodeType::Message1(m) => next1.push(m.clone()),
+            NodeType::Message2(m) => next2.push(m.clone()),
+            NodeType::Player1(_) | NodeType::Player2(_) | NodeType::Random(_) | NodeType::End => {}
+        }
+        histories_rec(tree, child, &next1, &next2, histories);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct Card(i32);
+
+    impl Display for Card {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Serializable for Card {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = Card;
+        type Choice = Card;
+    }
+
+    // P1 is dealt one of 8 cards, keeps or swaps it for the next one, and then sees it again before betting it wins
+    #[derive(Clone, Debug, Default)]
+    struct Deal {
+        card: Option<i32>,
+        swapped: bool,
+    }
+
+    impl Game for Deal {
+        type P1 = Traits;
+        type P2 = Traits;
+        type RandomChoice = Card;
+        fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+            let cards: Vec<Card> = (0..8).map(Card).collect();
+            let card = *self.card.get_or_insert(g.random(&vec![1./8.; 8], &cards)? as i32);
+            g.p1_message(&Card(card))?;
+            self.swapped = g.p1_choice(&vec![Card(0), Card(1)])? == 1;
+            let card = if self.swapped { (card + 1) % 8 } else { card };
+            g.p1_message(&Card(card))?;
+            let bet = g.p1_choice(&vec![Card(0), Card(1)])? == 1;
+            g.end(if bet { (card - 4) as f64 } else { 0. });
+            None
+        }
+    }
+
+    #[test]
+    fn kmeans_is_reproducible_and_sees_every_message() {
+        let tree = Tree::new(Deal::default());
+        let abstraction = |k| KMeansAbstraction::new(&tree, (k, 1), 20, &mut StdRng::seed_from_u64(3));
+        assert_eq!(abstraction(4).centroids, abstraction(4).centroids);
+        // 8 deals, then 16 ways to have been dealt one card and seen another at the bet, each its own starting point
+        assert_eq!(24, abstraction(30).centroids.0.len());
+    }
+}
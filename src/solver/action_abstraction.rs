@@ -0,0 +1,307 @@
+use super::*;
+use std::collections::HashSet;
+
+// Which of a player's choices trees are built with. translate maps a message reporting an opponent action left out
+// of the abstraction, such as an unusual bet size, onto the message of a kept action
+pub trait ActionAbstraction<P: PlayerTraits> {
+    // Indices into v, increasing and not empty
+    fn restrict(&self, v: &[P::Choice]) -> Vec<usize>;
+
+    fn translate(&self, m: &P::Message) -> P::Message {
+        m.clone()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoActionAbstraction;
+
+impl<P: PlayerTraits> ActionAbstraction<P> for NoActionAbstraction {
+    fn restrict(&self, v: &[P::Choice]) -> Vec<usize> {
+        (0..v.len()).collect()
+    }
+}
+
+// Keeps the choices whose Display is listed. A choice list with none of them listed is an error, as the tree would
+// have nothing to choose there
+#[derive(Clone, Debug, Default)]
+pub struct KeepChoices {
+    pub keep: HashSet<String>,
+}
+
+impl KeepChoices {
+    pub fn new<S: ToString>(keep: impl IntoIterator<Item = S>) -> KeepChoices {
+        KeepChoices { keep: keep.into_iter().map(|s| s.to_string()).collect() }
+    }
+}
+
+impl<P: PlayerTraits> ActionAbstraction<P> for KeepChoices {
+    fn restrict(&self, v: &[P::Choice]) -> Vec<usize> {
+        let kept: Vec<usize> = (0..v.len()).filter(|&i| self.keep.contains(&v[i].to_string())).collect();
+        assert!(!kept.is_empty(), "choices {:?} should include one of {:?}", v, self.keep);
+        kept
+    }
+}
+
+impl KeepChoices {
+    // Choices are only known by their Display here, so the messages to translate onto are given with a distance
+    pub fn with_translation<M, D: Fn(&M, &M) -> f64>(self, kept: Vec<M>, distance: D) -> TranslateNearest<KeepChoices, M, D> {
+        TranslateNearest::new(self, kept, distance)
+    }
+}
+
+// Restricts like abstraction, and translates a message onto the kept message nearest to it by distance, such as an
+// unusual bet size onto the closest size the tree was built with. Only messages of the same Serializable kind as the
+// kept ones are actions to translate; others, such as cards, pass through unchanged
+#[derive(Clone, Debug)]
+pub struct TranslateNearest<A, M, D> {
+    pub abstraction: A,
+    pub kept: Vec<M>,
+    pub distance: D,
+}
+
+impl<A, M, D> TranslateNearest<A, M, D> {
+    pub fn new(abstraction: A, kept: Vec<M>, distance: D) -> TranslateNearest<A, M, D> {
+        assert!(!kept.is_empty(), "there should be a kept message to translate onto");
+        TranslateNearest { abstraction, kept, distance }
+    }
+}
+
+impl<P: PlayerTraits, A: ActionAbstraction<P>, D: Fn(&P::Message, &P::Message) -> f64> ActionAbstraction<P> for TranslateNearest<A, P::Message, D> {
+    fn restrict(&self, v: &[P::Choice]) -> Vec<usize> {
+        self.abstraction.restrict(v)
+    }
+
+    // Ties go to the earlier kept message
+    fn translate(&self, m: &P::Message) -> P::Message {
+        let kind = m.serialize().0;
+        self.kept.iter()
+            .filter(|k| k.serialize().0 == kind)
+            .map(|k| ((self.distance)(m, k), k))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or_else(|| m.clone(), |(_, k)| k.clone())
+    }
+}
+
+// The game with every choice list cut down by the players' abstractions, to be expanded and solved in its place
+#[derive(Debug)]
+pub struct Restricted<G: Game, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> {
+    pub game: G,
+    pub abstraction1: Arc<A1>,
+    pub abstraction2: Arc<A2>,
+}
+
+impl<G: Game, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> Restricted<G, A1, A2> {
+    pub fn new(game: G, abstraction1: A1, abstraction2: A2) -> Restricted<G, A1, A2> {
+        Restricted { game, abstraction1: Arc::new(abstraction1), abstraction2: Arc::new(abstraction2) }
+    }
+}
+
+impl<G: Game + Clone, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> Clone for Restricted<G, A1, A2> {
+    fn clone(&self) -> Self {
+        Restricted { game: self.game.clone(), abstraction1: self.abstraction1.clone(), abstraction2: self.abstraction2.clone() }
+    }
+}
+
+// Only the game state, as the abstractions are shared by every node
+impl<G: Game + Hash, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> Hash for Restricted<G, A1, A2> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.game.hash(state)
+    }
+}
+
+struct RestrictedInterface<'a, G: Game, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> {
+    outer: &'a mut dyn GameInterface<Restricted<G, A1, A2>>,
+    abstraction1: &'a A1,
+    abstraction2: &'a A2,
+}
+
+fn kept<C: Clone>(v: &[C], keep: &[usize]) -> Vec<C> {
+    keep.iter().map(|&i| v[i].clone()).collect()
+}
+
+impl<G: Game, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> GameInterface<G> for RestrictedInterface<'_, G, A1, A2> {
+    fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize> {
+        self.outer.random(p, v)
+    }
+    fn p1_choice(&mut self, v: &Vec<<G::P1 as PlayerTraits>::Choice>) -> Option<usize> {
+        let keep = self.abstraction1.restrict(v);
+        Some(keep[self.outer.p1_choice(&kept(v, &keep))?])
+    }
+    fn p2_choice(&mut self, v: &Vec<<G::P2 as PlayerTraits>::Choice>) -> Option<usize> {
+        let keep = self.abstraction2.restrict(v);
+        Some(keep[self.outer.p2_choice(&kept(v, &keep))?])
+    }
+    fn p1_message(&mut self, msg: &<G::P1 as PlayerTraits>::Message) -> Option<()> {
+        self.outer.p1_message(msg)
+    }
+    fn p2_message(&mut self, msg: &<G::P2 as PlayerTraits>::Message) -> Option<()> {
+        self.outer.p2_message(msg)
+    }
+    fn end(&mut self, value: f64) {
+        self.outer.end(value)
+    }
+}
+
+impl<G: Game, A1: ActionAbstraction<G::P1>, A2: ActionAbstraction<G::P2>> Game for Restricted<G, A1, A2> {
+    type P1 = G::P1;
+    type P2 = G::P2;
+    type RandomChoice = G::RandomChoice;
+    fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+        let Restricted { game, abstraction1, abstraction2 } = self;
+        game.step(&mut RestrictedInterface { outer: g, abstraction1: &**abstraction1, abstraction2: &**abstraction2 })
+    }
+}
+
+// Plays the real game with a player built for the restricted one, such as a PolicyPlayer from its tree: messages
+// are translated and only the kept choices are offered
+pub struct TranslatingPlayer<A, P> {
+    pub abstraction: A,
+    pub inner: P,
+}
+
+impl<A, P> TranslatingPlayer<A, P> {
+    pub fn new(abstraction: A, inner: P) -> TranslatingPlayer<A, P> {
+        TranslatingPlayer { abstraction, inner }
+    }
+}
+
+impl<T: PlayerTraits, A: ActionAbstraction<T>, P: Player<T>> Player<T> for TranslatingPlayer<A, P> {
+    fn receive_message(&mut self, msg: &T::Message) {
+        self.inner.receive_message(&self.abstraction.translate(msg))
+    }
+
+    fn choose(&mut self, v: &Vec<T::Choice>) -> usize {
+        let keep = self.abstraction.restrict(v);
+        keep[self.inner.choose(&kept(v, &keep))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct N(i32);
+
+    impl Display for N {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Serializable for N {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = N;
+        type Choice = N;
+    }
+
+    // P1 bets 1 to 9 and P2 hears it, then calls for bet - 5 or folds for 1
+    #[derive(Clone, Debug, Default, Hash)]
+    struct Bet {
+        bet: Option<i32>,
+    }
+
+    impl Game for Bet {
+        type P1 = Traits;
+        type P2 = Traits;
+        type RandomChoice = N;
+        fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+            let bets: Vec<N> = (1..=9).map(N).collect();
+            let bet = *self.bet.get_or_insert(bets[g.p1_choice(&bets)?].0);
+            g.p2_message(&N(bet))?;
+            let call = g.p2_choice(&vec![N(0), N(1)])? == 0;
+            g.end(if call { (bet - 5) as f64 } else { 1. });
+            None
+        }
+    }
+
+    struct Bets(i32);
+
+    impl Player<Traits> for Bets {
+        fn receive_message(&mut self, _: &N) {}
+        fn choose(&mut self, v: &Vec<N>) -> usize {
+            v.iter().position(|c| *c == N(self.0)).expect("bet should be offered")
+        }
+    }
+
+    #[test]
+    fn off_tree_bet_is_answered_as_the_nearest_kept_one() {
+        let sizes = || KeepChoices::new(["2", "8"]);
+        let tree = Tree::new(Restricted::new(Bet::default(), sizes(), NoActionAbstraction));
+        assert_eq!(2, tree.num_children());
+        let (_, p2) = policy_tables(&tree);
+        // P2's own choices are all kept; only the bets it hears are translated
+        let translation = TranslateNearest::new(NoActionAbstraction, vec![N(2), N(8)], |a: &N, b: &N| (a.0 - b.0).abs() as f64);
+        let p2 = TranslatingPlayer::new(translation, PolicyPlayer::new(p2, 2));
+        // 9 is off the tree, and is played like 8, where P2 folds
+        let mut value = None;
+        let mut g = DefaultGameInterface {
+            game_type: PhantomData,
+            randomer: |_: &Vec<f64>, _: &Vec<N>| 0,
+            player1: Bets(9),
+            player2: p2,
+            ender: |v| value = Some(v),
+        };
+        run_game(&mut Bet::default(), &mut g);
+        assert_eq!(Some(1.), value);
+    }
+
+    #[test]
+    #[should_panic(expected = "should include one of")]
+    fn keeping_no_choice_is_an_error() {
+        Tree::new(Restricted::new(Bet::default(), KeepChoices::new(["10"]), NoActionAbstraction));
+    }
+
+    // A card and a bet, told apart by their kind
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    enum Heard {
+        Card(i32),
+        Bet(i32),
+    }
+
+    impl Display for Heard {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl Serializable for Heard {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1, 1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            match self {
+                Heard::Card(c) => (0, vec![*c]),
+                Heard::Bet(b) => (1, vec![*b]),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct HeardTraits;
+
+    impl PlayerTraits for HeardTraits {
+        type Message = Heard;
+        type Choice = N;
+    }
+
+    #[test]
+    fn only_actions_are_translated() {
+        let distance = |a: &Heard, b: &Heard| (a.serialize().1[0] - b.serialize().1[0]).abs() as f64;
+        let abstraction = TranslateNearest::new(NoActionAbstraction, vec![Heard::Bet(2), Heard::Bet(8)], distance);
+        let translate = |m| ActionAbstraction::<HeardTraits>::translate(&abstraction, &m);
+        assert_eq!(Heard::Bet(8), translate(Heard::Bet(9)));
+        assert_eq!(Heard::Card(9), translate(Heard::Card(9)));
+    }
+}
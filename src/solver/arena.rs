@@ -0,0 +1,471 @@
+use super::*;
+use super::explorer::Live;
+use std::collections::{HashMap, HashSet};
+
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+#[derive(Clone, Debug)]
+struct ArenaNode {
+    node_type: u32,
+    // the first parent, when transpositions make this node shared. The node's snapshot suffix runs through it
+    parent: u32,
+    // children live in ArenaTree::children[children..children+num_children], NONE when unexpanded
+    children: u32,
+    num_children: u32,
+    // the snapshot the node replays from, and how many choices past it the node is
+    snapshot: u32,
+    depth: u32,
+    prob: u32,
+    // NaN when unknown
+    value: f64,
+}
+
+// Node types are interned, so nodes sharing a choice list or message share one NodeType
+#[derive(Clone, Debug)]
+struct Interner<G: Game> {
+    messages1: HashMap<<G::P1 as PlayerTraits>::Message, u32>,
+    messages2: HashMap<<G::P2 as PlayerTraits>::Message, u32>,
+    choices1: HashMap<Vec<<G::P1 as PlayerTraits>::Choice>, u32>,
+    choices2: HashMap<Vec<<G::P2 as PlayerTraits>::Choice>, u32>,
+    end: Option<u32>,
+}
+
+// A Tree stored in flat vectors, with node types interned and paths shared through parent links.
+// With expand_full_merged it becomes a DAG, where a child slot may point to a node first reached through another path.
+// The solvers don't know about sharing: they walk a shared node once per path to it, so solving takes as long as on
+// the tree, and each path writes its solution to the node in turn, the last one staying. Under history_key every
+// path there has the same information sets, so they all write the same solution; see state_key for when they don't
+#[derive(Clone, Debug)]
+pub struct ArenaTree<G: Game> {
+    nodes: Vec<ArenaNode>,
+    // NodeId(0) until descend() moves it; paths start here
+    root: NodeId,
+    // After descend() in a DAG, a parent below the root for the shared nodes whose first parent is not
+    below_root: HashMap<NodeId, NodeId>,
+    // whether expand_full_merged has shared a node yet
+    merged: bool,
+    children: Vec<u32>,
+    probs: Vec<f64>,
+    node_types: Vec<NodeType<G>>,
+    snapshots: Vec<Arc<Snapshot<G>>>,
+    interner: Interner<G>,
+}
+
+impl<G: Game> ArenaTree<G> {
+    fn empty() -> ArenaTree<G> {
+        ArenaTree {
+            nodes: Vec::new(),
+            root: NodeId(0),
+            below_root: HashMap::new(),
+            merged: false,
+            children: Vec::new(),
+            probs: Vec::new(),
+            node_types: Vec::new(),
+            snapshots: Vec::new(),
+            interner: Interner {
+                messages1: HashMap::new(),
+                messages2: HashMap::new(),
+                choices1: HashMap::new(),
+                choices2: HashMap::new(),
+                end: None,
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn root_id(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        let p = self.nodes[id.0 as usize].parent;
+        (p != NONE).then_some(NodeId(p))
+    }
+
+    pub fn child_id(&self, id: NodeId, i: usize) -> Option<NodeId> {
+        let node = &self.nodes[id.0 as usize];
+        assert!(i < node.num_children as usize, "child index should be in range");
+        let c = self.children[node.children as usize + i];
+        (c != NONE).then_some(NodeId(c))
+    }
+
+    // Child indices from the root to id. In a DAG this is one of the paths there
+    pub fn path(&self, id: NodeId) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut curr = id;
+        while curr != self.root {
+            let parent = self.below_root.get(&curr).copied().or_else(|| self.parent(curr)).expect("node should be below the root");
+            path.push(self.index_in_parent(parent, curr));
+            curr = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    // Child indices of the last n first-parent links above id
+    fn first_parent_path(&self, id: NodeId, n: usize) -> Vec<usize> {
+        let mut path = Vec::with_capacity(n);
+        let mut curr = id;
+        for _ in 0..n {
+            let parent = self.parent(curr).expect("snapshot should be above the node");
+            path.push(self.index_in_parent(parent, curr));
+            curr = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    // Finds a parent below the root for every shared node whose first parent is no longer there
+    fn find_parents_below_root(&mut self) {
+        let mut parents = HashMap::new();
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            for i in 0..self.num_children(id) {
+                if let Some(c) = self.child_id(id, i) {
+                    if c != self.root && !parents.contains_key(&c) {
+                        parents.insert(c, id);
+                        stack.push(c);
+                    }
+                }
+            }
+        }
+        self.below_root = parents.iter()
+            .filter(|(c, _)| self.parent(**c).is_none_or(|p| p != self.root && !parents.contains_key(&p)))
+            .map(|(&c, &p)| (c, p))
+            .collect();
+    }
+
+    fn index_in_parent(&self, parent: NodeId, child: NodeId) -> usize {
+        let p = &self.nodes[parent.0 as usize];
+        self.children[p.children as usize..(p.children + p.num_children) as usize].iter()
+            .position(|&c| c == child.0)
+            .expect("child should be linked from its parent")
+    }
+
+    pub fn histories(&self, id: NodeId) -> Histories {
+        let mut hist = Histories::default();
+        let mut curr = self.root_id();
+        for i in self.path(id) {
+            hist = hist.child(self.node_type(curr), i);
+            curr = self.child_id(curr, i).expect("path should be expanded");
+        }
+        hist
+    }
+
+    // The snapshot and the choices since it, as a Tree node stores them. The snapshot was taken on the way to the
+    // node's first parent, which may lie on another path than the one from the root
+    fn snapshot_path(&self, id: NodeId) -> NodePath<G> {
+        let node = &self.nodes[id.0 as usize];
+        (self.snapshots[node.snapshot as usize].clone(), self.first_parent_path(id, node.depth as usize))
+    }
+}
+
+impl<G: Game + Clone> ArenaTree<G> {
+    fn intern(&mut self, node_type: &NodeType<G>) -> u32 {
+        let next = self.node_types.len() as u32;
+        let id = match node_type {
+            NodeType::Message1(m) => *self.interner.messages1.entry(m.clone()).or_insert(next),
+            NodeType::Message2(m) => *self.interner.messages2.entry(m.clone()).or_insert(next),
+            NodeType::Player1(c) => *self.interner.choices1.entry(c.clone()).or_insert(next),
+            NodeType::Player2(c) => *self.interner.choices2.entry(c.clone()).or_insert(next),
+            NodeType::Random(_) => next,
+            NodeType::End => *self.interner.end.get_or_insert(next),
+        };
+        if id == next {
+            self.node_types.push(node_type.clone());
+        }
+        id
+    }
+
+    // Adds a single node made by the explorer, linking it as child i of parent
+    fn push(&mut self, node: &Tree<G>, parent: Option<(NodeId, usize)>) -> NodeId {
+        let id = self.nodes.len() as u32;
+        let snapshot = match parent.map(|(p, _)| self.nodes[p.0 as usize].snapshot) {
+            Some(s) if Arc::ptr_eq(&self.snapshots[s as usize], &node.path.0) => s,
+            _ => {
+                self.snapshots.push(node.path.0.clone());
+                self.snapshots.len() as u32 - 1
+            }
+        };
+        let prob = match &node.prob {
+            Some(p) => {
+                self.probs.extend_from_slice(p);
+                (self.probs.len() - p.len()) as u32
+            }
+            None => NONE,
+        };
+        let node_type = self.intern(&node.node_type);
+        self.nodes.push(ArenaNode {
+            node_type,
+            parent: parent.map_or(NONE, |(p, _)| p.0),
+            children: self.children.len() as u32,
+            num_children: node.children.len() as u32,
+            snapshot,
+            depth: node.path.1.len() as u32,
+            prob,
+            value: node.value.unwrap_or(f64::NAN),
+        });
+        self.children.extend(std::iter::repeat_n(NONE, node.children.len()));
+        if let Some((p, i)) = parent {
+            let slot = self.nodes[p.0 as usize].children as usize + i;
+            self.children[slot] = id;
+        }
+        NodeId(id)
+    }
+
+    pub fn from_tree(tree: &Tree<G>) -> ArenaTree<G> {
+        let mut arena = ArenaTree::empty();
+        arena.push_rec(tree, None);
+        arena
+    }
+
+    fn push_rec(&mut self, node: &Tree<G>, parent: Option<(NodeId, usize)>) {
+        let id = self.push(node, parent);
+        for i in 0..node.children.len() {
+            if let Some(child) = &node.children[i] {
+                self.push_rec(child, Some((id, i)));
+            }
+        }
+    }
+
+    pub fn to_tree(&self) -> Tree<G> {
+        self.to_tree_rec(self.root_id())
+    }
+
+    fn to_tree_rec(&self, id: NodeId) -> Tree<G> {
+        let node = &self.nodes[id.0 as usize];
+        Tree {
+            node_type: self.node_types[node.node_type as usize].clone(),
+            children: (0..node.num_children as usize).map(|i| self.child_id(id, i).map(|c| self.to_tree_rec(c))).collect(),
+            path: self.snapshot_path(id),
+            value: self.value(id),
+            prob: self.prob(id).map(|p| p.to_vec()),
+        }
+    }
+}
+
+impl<G: Game + Clone + Debug> ArenaTree<G> {
+    pub fn new(g: G) -> ArenaTree<G> {
+        ArenaTree::new_observed(g, &mut NoObserver)
+    }
+
+    pub fn new_root(g: G) -> ArenaTree<G> {
+        ArenaTree::from_tree(&Tree::new_root(g))
+    }
+
+    pub fn new_observed(g: G, obs: &mut dyn Observer<G>) -> ArenaTree<G> {
+        let mut tree = ArenaTree::new_root(g);
+        tree.expand_full_observed(tree.root_id(), obs);
+        exact::solve_observed(&mut tree, obs);
+        tree
+    }
+
+    pub fn expand(&mut self, id: NodeId, child: usize) -> NodeId {
+        self.expand_observed(id, child, &mut NoObserver)
+    }
+
+    pub fn expand_observed(&mut self, id: NodeId, child: usize, obs: &mut dyn Observer<G>) -> NodeId {
+        let new_node = self.make_child(id, child);
+        obs.expand(&new_node);
+        self.push(&new_node, Some((id, child)))
+    }
+
+    fn make_child(&self, id: NodeId, child: usize) -> Tree<G> {
+        assert!(self.child_id(id, child).is_none(), "should not expand already expanded child");
+        let (snapshot, mut suffix) = self.snapshot_path(id);
+        suffix.push(child);
+        explorer::make_node(snapshot, suffix)
+    }
+
+    // The live state of id, unless the expansion already carried it there
+    fn live(&self, id: NodeId, live: Option<Live<G>>) -> Live<G> {
+        live.unwrap_or_else(|| explorer::resume(&self.snapshot_path(id)))
+    }
+
+    // Like expand_full, but nodes at the start of a step with the same key are merged into one, turning the tree into a DAG.
+    // Returns how many transpositions were merged.
+    pub fn expand_full_merged<K: Hash + Eq>(&mut self, id: NodeId, key: impl Fn(&G, &Histories) -> Option<K>) -> usize {
+        let mut seen: HashMap<K, NodeId> = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut merged = 0;
+        let mut stack = vec![(id, self.histories(id), None)];
+        while let Some((id, hist, live)) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let live = self.live(id, live);
+            let path = self.snapshot_path(id);
+            for i in (0..self.nodes[id.0 as usize].num_children as usize).rev() {
+                let child_hist = hist.child(self.node_type(id), i);
+                if let Some(c) = self.child_id(id, i) {
+                    stack.push((c, child_hist, None));
+                    continue;
+                }
+                let (new_node, child_live) = explorer::make_child(&path, &live, i);
+                let k = if child_live.local.is_empty() { key(&child_live.start, &child_hist) } else { None };
+                match k.as_ref().and_then(|k| seen.get(k)) {
+                    Some(&existing) => {
+                        let slot = self.nodes[id.0 as usize].children as usize + i;
+                        self.children[slot] = existing.0;
+                        self.merged = true;
+                        merged += 1;
+                    }
+                    None => {
+                        let c = self.push(&new_node, Some((id, i)));
+                        if let Some(k) = k {
+                            seen.insert(k, c);
+                        }
+                        stack.push((c, child_hist, Some(child_live)));
+                    }
+                }
+            }
+        }
+        merged
+    }
+
+    pub fn expand_full(&mut self, id: NodeId) {
+        self.expand_full_observed(id, &mut NoObserver)
+    }
+
+    pub fn expand_full_observed(&mut self, id: NodeId, obs: &mut dyn Observer<G>) {
+        let mut stack = vec![(id, None)];
+        while let Some((id, live)) = stack.pop() {
+            let live = self.live(id, live);
+            let path = self.snapshot_path(id);
+            for i in (0..self.nodes[id.0 as usize].num_children as usize).rev() {
+                match self.child_id(id, i) {
+                    Some(c) => stack.push((c, None)),
+                    None => {
+                        let (new_node, child_live) = explorer::make_child(&path, &live, i);
+                        obs.expand(&new_node);
+                        stack.push((self.push(&new_node, Some((id, i))), Some(child_live)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<G: Game> GameTree<G> for ArenaTree<G> {
+    type Node<'a> = NodeId where G: 'a;
+
+    fn root(&self) -> NodeId {
+        self.root_id()
+    }
+    fn node_type(&self, n: NodeId) -> &NodeType<G> {
+        &self.node_types[self.nodes[n.0 as usize].node_type as usize]
+    }
+    fn num_children(&self, n: NodeId) -> usize {
+        self.nodes[n.0 as usize].num_children as usize
+    }
+    fn child(&self, n: NodeId, i: usize) -> Option<NodeId> {
+        self.child_id(n, i)
+    }
+    fn value(&self, n: NodeId) -> Option<f64> {
+        let v = self.nodes[n.0 as usize].value;
+        (!v.is_nan()).then_some(v)
+    }
+    fn prob(&self, n: NodeId) -> Option<&[f64]> {
+        let node = &self.nodes[n.0 as usize];
+        (node.prob != NONE).then(|| &self.probs[node.prob as usize..(node.prob + node.num_children) as usize])
+    }
+    // The nodes above stay in the arena
+    fn descend(&mut self, i: usize) {
+        self.root = self.child_id(self.root, i).expect("child should be expanded");
+        if self.merged {
+            self.find_parents_below_root();
+        }
+    }
+    fn set_solution(&mut self, path: &[usize], prob: Vec<f64>, value: f64) {
+        let id = self.find(path).expect("solution path should be expanded");
+        let node = &mut self.nodes[id.0 as usize];
+        assert_eq!(node.num_children as usize, prob.len(), "prob should have one entry per child");
+        if node.prob == NONE {
+            node.prob = self.probs.len() as u32;
+            self.probs.extend_from_slice(&prob);
+        } else {
+            self.probs[node.prob as usize..(node.prob + node.num_children) as usize].copy_from_slice(&prob);
+        }
+        node.value = value;
+    }
+}
+
+// Merges nodes with the same game state and the same histories for both players, which is always safe
+pub fn history_key<G: Clone + Hash + Eq>(g: &G, hist: &Histories) -> Option<(G, Histories)> {
+    Some((g.clone(), hist.clone()))
+}
+
+// Merges nodes with the same game state regardless of how it was reached.
+// Only sound for perfect information games, where the state is all either player knows;
+// the solver still tells the paths apart, and the merged node keeps the strategy of the last path solved.
+pub fn state_key<G: Clone + Hash + Eq>(g: &G, _: &Histories) -> Option<G> {
+    Some(g.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+    use crate::game::tic_tac_toe::TicTacToe;
+
+    struct Script(Vec<usize>);
+
+    impl<T: PlayerTraits> Player<T> for Script {
+        fn receive_message(&mut self, _: &T::Message) {}
+        fn choose(&mut self, _: &Vec<T::Choice>) -> usize {
+            self.0.remove(0)
+        }
+    }
+
+    fn play<T: GameTree<RPS>>(tree: T) -> f64 {
+        let mut value = None;
+        run_game(&mut TreeGame::new(tree), &mut DefaultGameInterface {
+            game_type: PhantomData,
+            randomer: |_: &Vec<f64>, _: &Vec<_>| 0,
+            player1: Script(vec![0]),
+            player2: Script(vec![1]),
+            ender: |v| value = Some(v),
+        });
+        value.expect("game should end")
+    }
+
+    // X in a corner, O in the centre and X in the opposite corner is reached from either corner first
+    #[test]
+    fn paths_stay_below_the_root_after_descending_a_dag() {
+        let mut tree = ArenaTree::new_root(TicTacToe::new());
+        assert!(tree.expand_full_merged(tree.root_id(), state_key) > 0);
+        tree.descend(8);
+        let mut stack = vec![tree.root_id()];
+        let mut seen = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let path = tree.path(id);
+            assert_eq!(Some(id), tree.find(&path), "{path:?}");
+            tree.histories(id);
+            stack.extend((0..tree.num_children(id)).filter_map(|i| tree.child_id(id, i)));
+        }
+        assert!(seen.iter().any(|&id| tree.parent(id).is_some_and(|p| !seen.contains(&p))), "some node should be shared with another branch");
+    }
+
+    #[test]
+    fn tree_game_descends_both_representations() {
+        let tree = Tree::new(RPS::new());
+        let path = [0, 1];
+        let expected = tree.find(&path).and_then(|n| n.value()).expect("end node should have value");
+        assert_ne!(0., expected);
+        assert_eq!(expected, play(ArenaTree::from_tree(&tree)));
+        assert_eq!(expected, play(tree));
+    }
+}
@@ -0,0 +1,479 @@
+use crate::common::lp_solver::{DefaultSolver, Solver};
+use crate::common::multi::MultiGame;
+
+use super::*;
+use super::exact::{add_leaf_constraints, PlayerTree};
+use super::multi::Flat;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// A player's sequences over a Flat, keyed by its information sets, with the variables exact builds for them
+type Sequences = PlayerTree<DefaultSolver, usize>;
+
+struct Leaf {
+    chance: f64,
+    // Each player's sequence, by the variable of its realization weight
+    seq: [usize; 2],
+    payoffs: Vec<f64>,
+}
+
+// What building both players' sequences finds besides them
+struct Found {
+    leaves: Vec<Leaf>,
+    // By information set, the sequence leading to it and the sequences of its choices
+    info_sets: HashMap<usize, (usize, Vec<usize>)>,
+    // Pairs of P1's and P2's information sets with a node of one above a node of the other
+    connected: HashSet<(usize, usize)>,
+}
+
+// Player p's realization weights go to lps[strategies[p]] and their values, bounding their best response as in
+// exact, to the other one
+struct Build<'a> {
+    flat: &'a Flat,
+    lps: [&'a mut DefaultSolver; 2],
+    strategies: [usize; 2],
+    // Each player's information sets on the way to the current node
+    above: [Vec<usize>; 2],
+    found: Found,
+}
+
+impl Build<'_> {
+    fn lps(&mut self, p: usize) -> (&mut DefaultSolver, &mut DefaultSolver) {
+        let [a, b] = &mut self.lps;
+        if self.strategies[p] == 0 { (&mut **a, &mut **b) } else { (&mut **b, &mut **a) }
+    }
+
+    fn rec(&mut self, node: usize, seqs: [&mut Sequences; 2], chance: f64) {
+        let n = &self.flat.nodes[node];
+        if n.children.is_empty() {
+            let [p1, p2] = seqs;
+            p1.end_corresps.push((chance*n.payoffs[0], p2.strategy));
+            p2.end_corresps.push((chance*n.payoffs[1], p1.strategy));
+            self.found.leaves.push(Leaf { chance, seq: [p1.strategy, p2.strategy], payoffs: n.payoffs.clone() });
+            return;
+        }
+        if n.player == 0 {
+            let [p1, p2] = seqs;
+            for (i, &c) in n.children.iter().enumerate() {
+                self.rec(c, [&mut *p1, &mut *p2], chance*n.prob.get(i).cloned().unwrap_or(1.));
+            }
+            return;
+        }
+        let (p, i) = (n.player - 1, n.info_set);
+        for &j in &self.above[1 - p] {
+            self.found.connected.insert(if p == 0 { (i, j) } else { (j, i) });
+        }
+        let [mut own, mut other] = seqs;
+        if p == 1 {
+            std::mem::swap(&mut own, &mut other);
+        }
+        let parent = own.strategy;
+        let (s_strat, s_value) = self.lps(p);
+        // Each player maximizes their own payoff
+        let children = &mut own.get_children(i, n.children.len(), s_strat, s_value, 1.).0;
+        let vars = children.iter().map(|c| c.strategy).collect();
+        let (seen, _) = self.found.info_sets.entry(i).or_insert((parent, vars));
+        assert_eq!(*seen, parent, "game should have perfect recall at {}", self.flat.info_sets[i]);
+        self.above[p].push(i);
+        for (child, &c) in children.iter_mut().zip(&self.flat.nodes[node].children) {
+            let seqs = if p == 0 { [&mut *child, &mut *other] } else { [&mut *other, &mut *child] };
+            self.rec(c, seqs, chance);
+        }
+        self.above[p].pop();
+    }
+}
+
+fn sequences(flat: &Flat, lps: [&mut DefaultSolver; 2], strategies: [usize; 2]) -> ([Sequences; 2], Found) {
+    let found = Found { leaves: Vec::new(), info_sets: HashMap::new(), connected: HashSet::new() };
+    let mut build = Build { flat, lps, strategies, above: [Vec::new(), Vec::new()], found };
+    let mut seqs = [0, 1].map(|p| {
+        let (s_strat, s_value) = build.lps(p);
+        PlayerTree::new(s_strat, s_value)
+    });
+    let [p1, p2] = &mut seqs;
+    build.rec(0, [p1, p2], 1.);
+    (seqs, build.found)
+}
+
+// The information sets right after a sequence, in a fixed order
+fn next(seq: &Sequences) -> Vec<(usize, &Vec<Sequences>)> {
+    let mut ret: Vec<_> = seq.children.iter().map(|(&(i, _), (children, _))| (i, children)).collect();
+    ret.sort_by_key(|&(i, _)| i);
+    ret
+}
+
+// Sequence seq and every sequence of the same player below it
+fn subtree(seq: &Sequences) -> Vec<&Sequences> {
+    let mut ret = vec![seq];
+    for (_, children) in next(seq) {
+        ret.extend(children.iter().flat_map(subtree));
+    }
+    ret
+}
+
+// Reduced pure strategies of the player whose sequences start at root, as the sequences they play: one choice at every
+// information set their own earlier choices lead to. There are exponentially many
+fn plans(root: &Sequences) -> Vec<HashSet<usize>> {
+    let mut ret = Vec::new();
+    plans_rec(&mut next(root).into_iter().map(|(_, c)| c).collect(), &mut HashSet::from([root.strategy]), &mut ret);
+    ret
+}
+
+fn plans_rec(pending: &mut Vec<&Vec<Sequences>>, active: &mut HashSet<usize>, ret: &mut Vec<HashSet<usize>>) {
+    let Some(children) = pending.pop() else {
+        ret.push(active.clone());
+        return;
+    };
+    for child in children {
+        let len = pending.len();
+        active.insert(child.strategy);
+        pending.extend(next(child).into_iter().map(|(_, c)| c));
+        plans_rec(pending, active, ret);
+        pending.truncate(len);
+        active.remove(&child.strategy);
+    }
+    pending.push(children);
+}
+
+// Behavioral strategy at every information set from realization weights, uniform where they are all zero
+fn behavioral(flat: &Flat, node: usize, seqs: [&Sequences; 2], weight: &dyn Fn(usize, &Sequences) -> f64, strategies: &mut [Vec<f64>]) {
+    let n = &flat.nodes[node];
+    if n.player == 0 {
+        for &c in &n.children {
+            behavioral(flat, c, seqs, weight, strategies);
+        }
+        return;
+    }
+    let p = n.player - 1;
+    let children = &seqs[p].children[&(n.info_set, n.children.len())].0;
+    let total = weight(p, seqs[p]);
+    strategies[n.info_set] = if total > EPS {
+        children.iter().map(|c| weight(p, c)/total).collect()
+    } else {
+        vec_of_repeat(children.len(), 1./children.len() as f64)
+    };
+    for (child, &c) in children.iter().zip(&n.children) {
+        let mut next = seqs;
+        next[p] = child;
+        behavioral(flat, c, next, weight, strategies);
+    }
+}
+
+fn sorted(coeffs: BTreeMap<usize, f64>) -> Vec<(f64, usize)> {
+    coeffs.into_iter().map(|(v, c)| (c, v)).collect()
+}
+
+// Strong Stackelberg equilibrium of a two-player game: the leader commits to a behavioral strategy, and the follower
+// best responds, breaking ties in the leader's favor. The LP is exact's for the leader, whose realization weights bound
+// the follower's best response value through the follower's value variables. For every pure strategy of the follower,
+// it finds the best commitment that strategy is a best response to. Writes both strategies into the tree, returning
+// their tables. The follower's pure strategies are enumerated, and their number is exponential in the follower's
+// information sets; each costs up to two solves, one checking it can be a best response at all and one finding the
+// leader's commitment
+pub fn stackelberg<G: MultiGame>(tree: &mut MultiTree<G>, leader: usize) -> Vec<PolicyTable> {
+    assert_eq!(2, tree.num_players(), "stackelberg should be given a two-player game");
+    let flat = Flat::new(tree);
+    let (l, f) = (leader - 1, 2 - leader);
+    let mut s = DefaultSolver::new();
+    // only holds the leader's values and the follower's realization weights, it is never solved
+    let mut unused = DefaultSolver::new();
+    let mut strategies = [0, 0];
+    strategies[f] = 1;
+    let (mut seqs, found) = sequences(&flat, [&mut s, &mut unused], strategies);
+    s.add_constraint(&vec![(1., seqs[l].strategy)], Ordering::Equal, 1.);
+    add_leaf_constraints(&mut s, &mut seqs[f]);
+    let slack = s.new_var();
+    s.add_constraint(&vec![(1., slack)], Ordering::Greater, 0.);
+    let structure = s.num_constraints();
+    let mut best: Option<(f64, Vec<f64>, HashSet<usize>)> = None;
+    for plan in plans(&seqs[f]) {
+        s.truncate(structure);
+        // plan's value for the follower, plus slack, is at least their best response value
+        let mut row = BTreeMap::from([(slack, 1.), (seqs[f].weighted_value, -1.)]);
+        let mut goal = BTreeMap::new();
+        for leaf in found.leaves.iter().filter(|leaf| plan.contains(&leaf.seq[f])) {
+            *row.entry(leaf.seq[l]).or_insert(0.) += leaf.chance*leaf.payoffs[f];
+            *goal.entry(leaf.seq[l]).or_insert(0.) += leaf.chance*leaf.payoffs[l];
+        }
+        s.add_constraint(&sorted(row), Ordering::Greater, 0.);
+        if s.solve(vec![(-1., slack)])(slack) > EPS {
+            continue;
+        }
+        s.add_constraint(&vec![(1., slack)], Ordering::Less, 0.);
+        let goal = sorted(goal);
+        let vars = s.num_vars();
+        let sol = s.solve(goal.clone());
+        let value: f64 = goal.iter().map(|&(c, x)| c*sol(x)).sum();
+        if !matches!(&best, Some(b) if value <= b.0 + EPS) {
+            best = Some((value, (0..vars).map(sol).collect(), plan));
+        }
+    }
+    let (_, weights, plan) = best.expect("some follower strategy should be a best response");
+    let mut tables: Vec<Vec<f64>> = vec_of_repeat(flat.info_sets.len(), Vec::new());
+    let weight = |p: usize, seq: &Sequences| if p == l { weights[seq.strategy] } else if plan.contains(&seq.strategy) { 1. } else { 0. };
+    behavioral(&flat, 0, [&seqs[0], &seqs[1]], &weight, &mut tables);
+    flat.apply(tree, &tables)
+}
+
+// An extensive-form correlated equilibrium as a correlation plan: for pairs of sequences that can be recommended
+// together, the probability that both are. The mediator recommends at an information set only once it is reached
+#[derive(Clone, Debug)]
+pub struct CorrelatedPlan {
+    // Keyed by both players' sequences, as the (information set, choice) pairs recommended on the way
+    pub pairs: HashMap<[Vec<(String, usize)>; 2], f64>,
+    pub payoffs: Vec<f64>,
+}
+
+impl CorrelatedPlan {
+    // The mediator's probabilities for each choice at info_set, given the sequences it recommended to both players so
+    // far. A player who stopped following keeps their recommended sequence, not the one they played
+    pub fn recommend(&self, info_set: &InfoSet, recommended: &[Vec<(String, usize)>; 2]) -> Vec<f64> {
+        let n = info_set.choices.len();
+        let total = self.pairs.get(recommended).copied().unwrap_or(0.);
+        if total <= EPS {
+            return vec_of_repeat(n, 1./n as f64);
+        }
+        (0..n).map(|a| {
+            let mut next = recommended.clone();
+            next[info_set.player - 1].push((info_set.to_string(), a));
+            self.pairs.get(&next).copied().unwrap_or(0.)/total
+        }).collect()
+    }
+}
+
+// The correlation plan's variable for each relevant pair of sequences. A sequence's own realization weight is its
+// pair with the other player's empty sequence
+struct Plan<'a> {
+    lp: &'a mut DefaultSolver,
+    roots: [usize; 2],
+    pairs: HashMap<(usize, usize), usize>,
+}
+
+impl Plan<'_> {
+    fn z(&mut self, seq: [usize; 2]) -> usize {
+        if seq[1] == self.roots[1] {
+            return seq[0];
+        }
+        if seq[0] == self.roots[0] {
+            return seq[1];
+        }
+        let lp = &mut *self.lp;
+        *self.pairs.entry((seq[0], seq[1])).or_insert_with(|| {
+            let z = lp.new_var();
+            lp.add_constraint(&vec![(1., z)], Ordering::Greater, 0.);
+            z
+        })
+    }
+
+    // The pair of p's sequence own and the other player's sequence other
+    fn z_of(&mut self, p: usize, own: usize, other: usize) -> usize {
+        self.z(if p == 0 { [own, other] } else { [other, own] })
+    }
+
+    // Keeps each of p's sequences below seq from paying more than following every recommendation from seq on, against
+    // the other player's recommendations given seq
+    fn incentives(&mut self, p: usize, seq: &Sequences, leaves_at: &HashMap<usize, Vec<&Leaf>>) {
+        for (_, children) in next(seq) {
+            for child in children {
+                let mut follow = BTreeMap::new();
+                for s in subtree(child) {
+                    for leaf in leaves_at.get(&s.strategy).into_iter().flatten() {
+                        *follow.entry(self.z(leaf.seq)).or_insert(0.) += leaf.chance*leaf.payoffs[p];
+                    }
+                }
+                let v = self.deviation(p, child.strategy, children, leaves_at);
+                *follow.entry(v).or_insert(0.) -= 1.;
+                self.lp.add_constraint(&sorted(follow), Ordering::Greater, 0.);
+                self.incentives(p, child, leaves_at);
+            }
+        }
+    }
+
+    // The best value p gets from the information set with these choices on, ignoring every recommendation after
+    // recommended, like the dual of p's sequence form LP there
+    fn deviation(&mut self, p: usize, recommended: usize, choices: &[Sequences], leaves_at: &HashMap<usize, Vec<&Leaf>>) -> usize {
+        let v = self.lp.new_var();
+        for choice in choices {
+            let mut row = BTreeMap::from([(v, 1.)]);
+            for leaf in leaves_at.get(&choice.strategy).into_iter().flatten() {
+                *row.entry(self.z_of(p, recommended, leaf.seq[1 - p])).or_insert(0.) -= leaf.chance*leaf.payoffs[p];
+            }
+            for (_, children) in next(choice) {
+                *row.entry(self.deviation(p, recommended, children, leaves_at)).or_insert(0.) -= 1.;
+            }
+            self.lp.add_constraint(&sorted(row), Ordering::Greater, 0.);
+        }
+        v
+    }
+}
+
+// Each sequence as the (information set, choice) pairs on the way to it
+fn labels(flat: &Flat, seq: &Sequences, label: &[(String, usize)], ret: &mut HashMap<usize, Vec<(String, usize)>>) {
+    ret.insert(seq.strategy, label.to_vec());
+    for (i, children) in next(seq) {
+        for (a, child) in children.iter().enumerate() {
+            let mut next = label.to_vec();
+            next.push((flat.info_sets[i].to_string(), a));
+            labels(flat, child, &next, ret);
+        }
+    }
+}
+
+// Extensive-form correlated equilibrium of a two-player game maximizing the payoffs weighted by weights, with the
+// polynomial correlation plan LP of von Stengel and Forges (2008). Its variables are exact's realization weights of
+// both players' sequences and one per pair of sequences whose information sets lie on a common path. A player
+// recommended a choice may ignore it and every later recommendation, and the LP keeps that from paying off: the best
+// deviation is bounded like the dual of the player's sequence form LP, once per recommended sequence. Without chance
+// moves the plan's constraints describe exactly the distributions over pairs of pure strategies; with them, where the
+// problem is NP-hard, the LP is a relaxation whose plan the mediator still follows
+pub fn correlated<G: MultiGame>(tree: &MultiTree<G>, weights: &[f64]) -> CorrelatedPlan {
+    assert_eq!(2, tree.num_players(), "correlated should be given a two-player game");
+    let flat = Flat::new(tree);
+    let mut s = DefaultSolver::new();
+    // only holds both players' values, it is never solved
+    let mut unused = DefaultSolver::new();
+    let (seqs, found) = sequences(&flat, [&mut s, &mut unused], [0, 0]);
+    for seq in &seqs {
+        s.add_constraint(&vec![(1., seq.strategy)], Ordering::Equal, 1.);
+    }
+    let mut plan = Plan { lp: &mut s, roots: [seqs[0].strategy, seqs[1].strategy], pairs: HashMap::new() };
+    // The realization weights are consistent given each sequence of the other player at a connected information set
+    let mut connected: Vec<_> = found.connected.iter().collect();
+    connected.sort();
+    for &(i1, i2) in connected {
+        let ((parent1, choices1), (parent2, choices2)) = (&found.info_sets[&i1], &found.info_sets[&i2]);
+        for &b in choices2 {
+            let mut row = vec![(1., plan.z([*parent1, b]))];
+            row.extend(choices1.iter().map(|&a| (-1., plan.z([a, b]))));
+            plan.lp.add_constraint(&row, Ordering::Equal, 0.);
+        }
+        for &a in choices1 {
+            let mut row = vec![(1., plan.z([a, *parent2]))];
+            row.extend(choices2.iter().map(|&b| (-1., plan.z([a, b]))));
+            plan.lp.add_constraint(&row, Ordering::Equal, 0.);
+        }
+    }
+    for (p, seq) in seqs.iter().enumerate() {
+        let mut leaves_at: HashMap<usize, Vec<&Leaf>> = HashMap::new();
+        for leaf in &found.leaves {
+            leaves_at.entry(leaf.seq[p]).or_default().push(leaf);
+        }
+        plan.incentives(p, seq, &leaves_at);
+    }
+    let mut goal = BTreeMap::new();
+    for leaf in &found.leaves {
+        *goal.entry(plan.z(leaf.seq)).or_insert(0.) += leaf.chance*leaf.payoffs.iter().zip(weights).map(|(u, w)| u*w).sum::<f64>();
+    }
+    let Plan { pairs, roots, .. } = plan;
+    let sol = s.solve(sorted(goal));
+    let mut payoffs = vec![0., 0.];
+    for leaf in &found.leaves {
+        let z = match (leaf.seq[0] == roots[0], leaf.seq[1] == roots[1]) {
+            (_, true) => leaf.seq[0],
+            (true, false) => leaf.seq[1],
+            (false, false) => pairs[&(leaf.seq[0], leaf.seq[1])],
+        };
+        for (total, u) in payoffs.iter_mut().zip(&leaf.payoffs) {
+            *total += sol(z)*leaf.chance*u;
+        }
+    }
+    let [label1, label2] = seqs.each_ref().map(|seq| {
+        let mut label = HashMap::new();
+        labels(&flat, seq, &[], &mut label);
+        label
+    });
+    let mut all: Vec<((usize, usize), usize)> = pairs.into_iter().collect();
+    all.extend(label1.keys().map(|&a| ((a, roots[1]), a)));
+    all.extend(label2.keys().filter(|&&b| b != roots[1]).map(|&b| ((roots[0], b), b)));
+    let pairs = all.into_iter().filter(|&(_, z)| sol(z) > EPS)
+        .map(|((a, b), z)| ([label1[&a].clone(), label2[&b].clone()], sol(z)))
+        .collect();
+    CorrelatedPlan { pairs, payoffs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::multi::MultiGameInterface;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct N(i32);
+
+    impl Display for N {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Serializable for N {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = N;
+        type Choice = N;
+    }
+
+    // P1 picks a row and P2, without seeing it, a column
+    #[derive(Clone, Debug)]
+    struct Matrix {
+        pay: Vec<Vec<(f64, f64)>>,
+        row: Option<usize>,
+    }
+
+    impl MultiGame for Matrix {
+        type Traits = Traits;
+        type RandomChoice = N;
+        fn num_players(&self) -> usize {
+            2
+        }
+        fn step(&mut self, g: &mut dyn MultiGameInterface<Self>) -> Option<()> {
+            let Some(row) = self.row else {
+                self.row = Some(g.choice(1, &(0..self.pay.len() as i32).map(N).collect())?);
+                return Some(());
+            };
+            let column = g.choice(2, &(0..self.pay[row].len() as i32).map(N).collect())?;
+            let (a, b) = self.pay[row][column];
+            g.end(&[a, b]);
+            None
+        }
+    }
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    #[test]
+    fn leader_commits_to_mixing() {
+        let game = Matrix { pay: vec![vec![(2., 1.), (4., 0.)], vec![(1., 0.), (3., 2.)]], row: None };
+        let mut tree = MultiTree::new(game);
+        let tables = stackelberg(&mut tree, 1);
+        assert!(close(tree.payoffs().expect("tree should be solved"), &[11./3., 2./3.]));
+        let leader: Vec<f64> = tables[0].entries.values().next().expect("P1 should choose").iter().map(|c| c.1).collect();
+        assert!(close(&leader, &[2./3., 1./3.]));
+        let follower: Vec<f64> = tables[1].entries.values().next().expect("P2 should choose").iter().map(|c| c.1).collect();
+        assert!(close(&follower, &[0., 1.]));
+    }
+
+    #[test]
+    fn mediator_avoids_the_crash_in_chicken() {
+        let game = Matrix { pay: vec![vec![(0., 0.), (7., 2.)], vec![(2., 7.), (6., 6.)]], row: None };
+        let tree = MultiTree::new(game);
+        let plan = correlated(&tree, &[1., 1.]);
+        assert!(close(&plan.payoffs, &[5.25, 5.25]));
+        let flat = Flat::new(&tree);
+        let (first, second) = (&flat.info_sets[0], &flat.info_sets[1]);
+        assert!(close(&plan.recommend(first, &[vec![], vec![]]), &[0.25, 0.75]));
+        let told = [vec![(first.to_string(), 1)], vec![]];
+        assert!(close(&plan.recommend(second, &told), &[1./3., 2./3.]));
+        let told = [vec![(first.to_string(), 0)], vec![]];
+        assert!(close(&plan.recommend(second, &told), &[0., 1.]));
+    }
+}
@@ -0,0 +1,172 @@
+use super::*;
+use crate::common::encoding::Encoder;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// One row per decision node of a player with a solution: the player's information state, the equilibrium
+// probabilities and a legality mask padded with zeros to the widest choice list, and the node's value for the player
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dataset {
+    pub player: usize,
+    pub width: usize,
+    pub actions: usize,
+    pub features: Vec<f32>,
+    pub policy: Vec<f32>,
+    pub mask: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+// A player's information state bags their messages, then their choices
+struct Sources<P: PlayerTraits> {
+    messages: Encoder<P::Message>,
+    choices: Encoder<P::Choice>,
+}
+
+impl<P: PlayerTraits> Sources<P> {
+    fn new() -> Sources<P> {
+        Sources { messages: Encoder::new(), choices: Encoder::new() }
+    }
+
+    fn width(&self) -> usize {
+        self.messages.payload_width() + self.choices.payload_width()
+    }
+
+    fn add_message(&self, state: &mut [f32], m: &P::Message) {
+        self.messages.add_to_bag(m, state).expect("serialized messages should match kind_sizes");
+    }
+
+    fn add_choice(&self, state: &mut [f32], c: &P::Choice) {
+        let offset = self.messages.payload_width();
+        self.choices.add_to_bag(c, &mut state[offset..]).expect("serialized choices should match kind_sizes");
+    }
+}
+
+type Row = (Vec<f32>, Vec<f64>, f64);
+
+impl Dataset {
+    fn from_rows(player: usize, width: usize, rows: Vec<Row>) -> Dataset {
+        let actions = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+        let mut ret = Dataset { player, width, actions, features: Vec::new(), policy: Vec::new(), mask: Vec::new(), values: Vec::new() };
+        for (features, prob, value) in rows {
+            ret.features.extend(features);
+            ret.policy.extend(prob.iter().map(|&p| p as f32).chain(std::iter::repeat(0.)).take(actions));
+            ret.mask.extend(std::iter::repeat_n(1., prob.len()).chain(std::iter::repeat(0.)).take(actions));
+            ret.values.push(if player == 1 { value } else { -value } as f32);
+        }
+        ret
+    }
+
+    pub fn rows(&self) -> usize {
+        self.values.len()
+    }
+
+    // Writes {prefix}_features.npy, _policy.npy, _mask.npy and _value.npy
+    pub fn write_npy(&self, prefix: &Path) -> io::Result<()> {
+        let file = |suffix: &str| {
+            let mut name = prefix.to_path_buf().into_os_string();
+            name.push(suffix);
+            File::create(name).map(BufWriter::new)
+        };
+        write_npy(file("_features.npy")?, &[self.rows(), self.width], &self.features)?;
+        write_npy(file("_policy.npy")?, &[self.rows(), self.actions], &self.policy)?;
+        write_npy(file("_mask.npy")?, &[self.rows(), self.actions], &self.mask)?;
+        write_npy(file("_value.npy")?, &[self.rows()], &self.values)
+    }
+}
+
+// NumPy format version 1.0 of little-endian f32s in C order
+pub fn write_npy<W: Write>(mut w: W, shape: &[usize], data: &[f32]) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), data.len(), "data should fill the shape");
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let shape = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {shape}, }}");
+    // The magic, version and length take 10 bytes, and the data should start 64-byte aligned
+    let total = (10 + header.len() + 1).div_ceil(64) * 64;
+    header.push_str(&" ".repeat(total - 10 - header.len() - 1));
+    header.push('\n');
+    w.write_all(b"\x93NUMPY\x01\x00")?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for x in data {
+        w.write_all(&x.to_le_bytes())?;
+    }
+    w.flush()
+}
+
+pub fn datasets<G: Game, T: GameTree<G>>(tree: &T) -> (Dataset, Dataset) {
+    let sources = (Sources::<G::P1>::new(), Sources::<G::P2>::new());
+    let widths = (sources.0.width(), sources.1.width());
+    let mut rows = (Vec::new(), Vec::new());
+    datasets_rec(tree, tree.root(), &sources, &(vec_of_repeat(widths.0, 0.), vec_of_repeat(widths.1, 0.)), &mut rows);
+    (Dataset::from_rows(1, widths.0, rows.0), Dataset::from_rows(2, widths.1, rows.1))
+}
+
+fn datasets_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, sources: &(Sources<G::P1>, Sources<G::P2>), states: &(Vec<f32>, Vec<f32>), rows: &mut (Vec<Row>, Vec<Row>)) {
+    let node_type = tree.node_type(node);
+    if let (Some(prob), Some(value)) = (tree.prob(node), tree.value(node)) {
+        match node_type {
+            NodeType::Player1(_) => rows.0.push((states.0.clone(), prob.to_vec(), value)),
+            NodeType::Player2(_) => rows.1.push((states.1.clone(), prob.to_vec(), value)),
+            _ => {}
+        }
+    }
+    for i in 0..tree.num_children(node) {
+        let Some(child) = tree.child(node, i) else { continue };
+        let mut next = states.clone();
+        match node_type {
+            NodeType::Message1(m) => sources.0.add_message(&mut next.0, m),
+            NodeType::Message2(m) => sources.1.add_message(&mut next.1, m),
+            NodeType::Player1(c) => sources.0.add_choice(&mut next.0, &c[i]),
+            NodeType::Player2(c) => sources.1.add_choice(&mut next.1, &c[i]),
+            NodeType::Random(_) | NodeType::End => {}
+        }
+        datasets_rec(tree, child, sources, &next, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    // The shape from the header and the data, checking what numpy checks when loading
+    fn read_npy(bytes: &[u8]) -> (String, Vec<f32>) {
+        assert_eq!(b"\x93NUMPY\x01\x00", &bytes[..8]);
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!(0, (10 + len) % 64);
+        let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': ("));
+        assert!(header.ends_with('\n'));
+        let shape = &header[header.find("'shape': ").unwrap() + 9..header.find(')').unwrap() + 1];
+        let data = bytes[10 + len..].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        (shape.to_string(), data)
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let data = [1., -2.5, 0., 3.25, 1e-3, 7.];
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[2, 3], &data).unwrap();
+        assert_eq!(("(2, 3)".to_string(), data.to_vec()), read_npy(&bytes));
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[6], &data).unwrap();
+        assert_eq!(("(6,)".to_string(), data.to_vec()), read_npy(&bytes));
+    }
+
+    #[test]
+    fn rows_follow_the_solved_tree() {
+        let tree = Tree::new(RPS::new());
+        let (p1, p2) = datasets(&tree);
+        // P2 decides once for each of P1's choices without seeing it, so their rows share an information state
+        assert_eq!((1, 3), (p1.rows(), p2.rows()));
+        assert_eq!((6, 3), (p2.width, p2.actions));
+        assert!(p2.features.iter().all(|&x| x == 0.));
+        assert!(p1.policy.iter().chain(&p2.policy).all(|p| (p - 1./3.).abs() < 1e-6));
+        assert!(p2.mask.iter().all(|&m| m == 1.));
+        assert!(p1.values.iter().chain(&p2.values).all(|v| v.abs() < 1e-6));
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[p2.rows(), p2.actions], &p2.policy).unwrap();
+        assert_eq!(("(3, 3)".to_string(), p2.policy.clone()), read_npy(&bytes));
+    }
+}
@@ -0,0 +1,125 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[derive(Clone, Debug)]
+pub struct DotOptions {
+    pub max_depth: Option<usize>,
+    pub prune_zero_prob: bool,
+    pub show_info_sets: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        DotOptions {
+            max_depth: None,
+            prune_zero_prob: true,
+            show_info_sets: true,
+        }
+    }
+}
+
+struct DotWriter<'a, W: Write> {
+    w: W,
+    opts: &'a DotOptions,
+    next_id: usize,
+    info_sets: BTreeMap<InfoSet, Vec<usize>>,
+}
+
+pub fn write_dot<G: Game, W: Write>(tree: &Tree<G>, opts: &DotOptions, w: W) -> io::Result<()> {
+    let mut dw = DotWriter { w, opts, next_id: 0, info_sets: BTreeMap::new() };
+    writeln!(dw.w, "digraph tree {{")?;
+    writeln!(dw.w, "  node [shape=box, fontname=monospace];")?;
+    dw.node(tree, &Histories::default(), 0)?;
+    if opts.show_info_sets {
+        for ids in dw.info_sets.values() {
+            for pair in ids.windows(2) {
+                writeln!(dw.w, "  n{} -> n{} [style=dashed, dir=none, constraint=false, color=gray];", pair[0], pair[1])?;
+            }
+        }
+    }
+    writeln!(dw.w, "}}")
+}
+
+pub fn to_dot<G: Game>(tree: &Tree<G>, opts: &DotOptions) -> String {
+    let mut buf = Vec::new();
+    write_dot(tree, opts, &mut buf).expect("writing to a Vec should not fail");
+    String::from_utf8(buf).expect("dot output should be utf8")
+}
+
+impl<'a, W: Write> DotWriter<'a, W> {
+    fn node<G: Game>(&mut self, node: &Tree<G>, hist: &Histories, depth: usize) -> io::Result<usize> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let value = node.value.map_or("?".to_string(), |v| format!("{v:.3}"));
+        writeln!(self.w, "  n{id} [label=\"{}\\nvalue: {value}\"];", escape(&node_label(&node.node_type)))?;
+        if let Some(info_set) = hist.info_set(&node.node_type) {
+            self.info_sets.entry(info_set).or_default().push(id);
+        }
+        if self.opts.max_depth.is_some_and(|d| depth >= d) {
+            return Ok(id);
+        }
+        for i in 0..node.children.len() {
+            let p = node.prob.as_ref().map(|p| p[i]);
+            if self.opts.prune_zero_prob && p.is_some_and(|p| p <= EPS) {
+                continue;
+            }
+            let mut label = node.node_type.child_label(i);
+            if let Some(p) = p {
+                label = format!("{label} ({p:.3})");
+            }
+            let child_id = match &node.children[i] {
+                Some(child) => self.node(child, &hist.child(&node.node_type, i), depth + 1)?,
+                None => {
+                    let child_id = self.next_id;
+                    self.next_id += 1;
+                    writeln!(self.w, "  n{child_id} [label=\"unexpanded\", style=dotted];")?;
+                    child_id
+                }
+            };
+            writeln!(self.w, "  n{id} -> n{child_id} [label=\"{}\"];", escape(&label))?;
+        }
+        Ok(id)
+    }
+}
+
+fn node_label<G: Game>(node_type: &NodeType<G>) -> String {
+    match node_type {
+        NodeType::Message1(m) => format!("Message1\n{m}"),
+        NodeType::Message2(m) => format!("Message2\n{m}"),
+        NodeType::Player1(_) => "Player1".to_string(),
+        NodeType::Player2(_) => "Player2".to_string(),
+        NodeType::Random(_) => "Random".to_string(),
+        NodeType::End => "End".to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    // Nodes, choice edges and information set edges in the output
+    fn counts(dot: &str) -> (usize, usize, usize) {
+        let lines: Vec<&str> = dot.lines().map(str::trim).filter(|l| l.starts_with('n') && l[1..].starts_with(|c: char| c.is_ascii_digit())).collect();
+        let nodes = lines.iter().filter(|l| !l.contains("->")).count();
+        let info_sets = lines.iter().filter(|l| l.contains("style=dashed")).count();
+        (nodes, lines.len() - nodes - info_sets, info_sets)
+    }
+
+    #[test]
+    fn draws_choices_and_information_sets() {
+        let tree = Tree::new(RPS::new());
+        let dot = to_dot(&tree, &DotOptions::default());
+        assert!(dot.starts_with("digraph tree {") && dot.trim_end().ends_with('}'));
+        // P2's three nodes share an information set
+        assert_eq!((13, 12, 2), counts(&dot));
+        assert!(dot.contains("[label=\"Player1\\nvalue: 0.000\"]"));
+        let opts = DotOptions { max_depth: Some(1), show_info_sets: false, ..DotOptions::default() };
+        assert_eq!((4, 3, 0), counts(&to_dot(&tree, &opts)));
+    }
+}
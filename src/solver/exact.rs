@@ -1,18 +1,26 @@
-use crate::common::lp_solver::{DefaultSolver, Solver};
+use crate::common::lp_solver::{Backend, Cbc, DualBackend, DualSolver, ModelStats, Solver, SparseSolver};
 
 use super::*;
+use super::abstraction::{Abstraction, NoAbstraction};
+use super::recall::assert_perfect_recall;
 use std::collections::HashMap;
 
-struct PlayerTree<S: Solver, P: PlayerTraits> {
-    children: HashMap<(Vec<P::Message>, usize), (Vec<PlayerTree<S, P>>, S::Variable)>,
-    strategy: S::Variable,
-    weighted_value: S::Variable,
-    end_corresps: Vec<(f64, S::Variable)>,
+type Children<S, K> = HashMap<(K, usize), (Vec<PlayerTree<S, K>>, <S as Solver>::Variable)>;
+
+// Keyed by the abstraction's bucket of every message the player got so far, and the number of choices
+pub(super) struct PlayerTree<S: Solver, K: Eq + Hash> {
+    pub(super) children: Children<S, K>,
+    pub(super) strategy: S::Variable,
+    pub(super) weighted_value: S::Variable,
+    pub(super) end_corresps: Vec<(f64, S::Variable)>,
     temp_end: Vec<(f64, S::Variable)>,
+    leaf: Option<S::Constraint>,
+    // See Refinement::Tremble
+    tremble: f64,
 }
 
-impl<S: Solver, P: PlayerTraits> PlayerTree<S, P> {
-    fn new(s_strat: &mut S, s_value: &mut S) -> PlayerTree<S, P> {
+impl<S: Solver, K: Eq + Hash> PlayerTree<S, K> {
+    pub(super) fn new(s_strat: &mut S, s_value: &mut S) -> PlayerTree<S, K> {
         let s = s_strat.new_var();
         s_strat.add_constraint(&vec![(1., s.clone())], Ordering::Greater, 0.);
         PlayerTree {
@@ -21,161 +29,555 @@ impl<S: Solver, P: PlayerTraits> PlayerTree<S, P> {
             weighted_value: s_value.new_var(),
             end_corresps: Vec::new(),
             temp_end: Vec::new(),
+            leaf: None,
+            tremble: 0.,
         }
     }
 
-    fn get_children(&mut self, m: Vec<P::Message>, c: usize, s_strat: &mut S, s_value: &mut S, direction: f64) -> &mut (Vec<PlayerTree<S, P>>, S::Variable) {
-        self.children.entry((m, c)).or_insert_with(|| {
-            let mut children: Vec<PlayerTree<S, P>> = std::iter::repeat_with(|| PlayerTree::new(s_strat, s_value)).take(c).collect();
+    pub(super) fn get_children(&mut self, bucket: K, c: usize, s_strat: &mut S, s_value: &mut S, direction: f64) -> &mut (Vec<PlayerTree<S, K>>, S::Variable) {
+        let tremble = self.tremble;
+        self.children.entry((bucket, c)).or_insert_with(|| {
+            let mut children: Vec<PlayerTree<S, K>> = std::iter::repeat_with(|| PlayerTree::new(s_strat, s_value)).take(c).collect();
             let root_val = s_value.new_var();
+            // With a tremble, the best choice only gets what the others leave of the probability
+            let best_val = if tremble > 0. { s_value.new_var() } else { root_val.clone() };
             let mut sum_vec = vec![(-1., self.strategy.clone())];
+            let mut tremble_vec = vec![(1., root_val.clone()), (-(1. - c as f64*tremble), best_val.clone())];
             for child in &mut children {
-                s_value.add_constraint(&vec![(direction, root_val.clone()), (-direction, child.weighted_value.clone())], Ordering::Greater, 0.);
+                child.tremble = tremble;
+                s_value.add_constraint(&vec![(direction, best_val.clone()), (-direction, child.weighted_value.clone())], Ordering::Greater, 0.);
                 sum_vec.push((1., child.strategy.clone()));
+                tremble_vec.push((-tremble, child.weighted_value.clone()));
             }
             s_strat.add_constraint(&sum_vec, Ordering::Equal, 0.);
+            if tremble > 0. {
+                assert!(tremble*(c as f64) < 1., "tremble should leave room for every choice");
+                s_value.add_constraint(&tremble_vec, Ordering::Equal, 0.);
+                for child in &children {
+                    s_strat.add_constraint(&vec![(1., child.strategy.clone()), (-tremble, self.strategy.clone())], Ordering::Greater, 0.);
+                }
+            }
             (children, root_val)
         })
     }
-}
 
-pub fn solve<G: Game + Clone>(root: &mut Tree<G>) {
-    let mut s1 = DefaultSolver::new();
-    let mut s2 = DefaultSolver::new();
-    let mut p1 = PlayerTree::new(&mut s2, &mut s1);
-    let mut p2 = PlayerTree::new(&mut s1, &mut s2);
-    explore_rec(&mut s1, &mut s2, &mut p1, &mut p2, &Some(root), 1.0, &Vec::new(), &Vec::new());
-    s1.add_constraint(&vec![(1., p1.strategy)], Ordering::Equal, 1.);
-    s2.add_constraint(&vec![(1., p2.strategy)], Ordering::Equal, 1.);
-    add_leaf_constraints(&mut s1, &p2);
-    add_leaf_constraints(&mut s2, &p1);
-    let sol1 = s1.solve(vec![(1., p2.weighted_value)]);
-    let sol2 = s2.solve(vec![(-1., p1.weighted_value)]);
-    extract_solution_rec::<DefaultSolver, G>(&p1, &p2, Some(root), &Vec::new(), &Vec::new(), &sol1, &sol2);
-}
-
-fn explore_rec<S: Solver, G: Game + Clone>(s1: &mut S, s2: &mut S, p1: &mut PlayerTree<S, G::P1>, p2: &mut PlayerTree<S, G::P2>, maybe_node: &Option<&Tree<G>>, nature: f64, msgs1: &Vec<<G::P1 as PlayerTraits>::Message>, msgs2: &Vec<<G::P2 as PlayerTraits>::Message>) {
-    match maybe_node {
-        None => {
-            p1.temp_end.push((nature*-1., p2.strategy.clone()));
-            p2.temp_end.push((nature*1., p1.strategy.clone()));
-        }
-        Some(node) => match node.node_type.clone() {
-            NodeType::Message1(m) => {
-                let mut next_msgs1 = msgs1.clone();
-                next_msgs1.push(m);
-                explore_rec(s1, s2, p1, p2, &node.children[0].as_ref(), nature, &next_msgs1, msgs2);
-            }
-            NodeType::Message2(m) => {
-                let mut next_msgs2 = msgs2.clone();
-                next_msgs2.push(m);
-                explore_rec(s1, s2, p1, p2, &node.children[0].as_ref(), nature, msgs1, &next_msgs2);
-            }
-            NodeType::Player1(c) => {
-                let children = &mut p1.get_children(msgs1.clone(), c.len(), s1, s2, 1.).0;
-                for i in 0..c.len() {
-                    explore_rec(s1, s2, &mut children[i], p2, &node.children[i].as_ref(), nature, &Vec::new(), msgs2);
-                }
-            }
-            NodeType::Player2(c) => {
-                let children = &mut p2.get_children(msgs2.clone(), c.len(), s2, s1, -1.).0;
-                for i in 0..c.len() {
-                    explore_rec(s1, s2, p1, &mut children[i], &node.children[i].as_ref(), nature, msgs1, &Vec::new());
-                }
+    fn clear_payoffs(&mut self) {
+        self.end_corresps.clear();
+        self.temp_end.clear();
+        self.leaf = None;
+        for c in self.children.values_mut() {
+            for child in &mut c.0 {
+                child.clear_payoffs();
             }
-            NodeType::Random(r) => {
-                for i in 0..r.len() {
-                    explore_rec(s1, s2, p1, p2, &node.children[i].as_ref(), nature*node.prob.as_ref().expect("random nodes should have prob")[i], msgs1, msgs2);
-                }
-            }
-            NodeType::End => {
-                p1.end_corresps.push((nature*node.value.expect("end nodes should have value"), p2.strategy.clone()));
-                p2.end_corresps.push((nature*node.value.expect("end nodes should have value"), p1.strategy.clone()));
-            },
         }
-    };
+    }
 }
 
-fn add_leaf_constraints<S: Solver, T: PlayerTraits>(s: &mut S, p: &PlayerTree<S, T>) {
-    let mut sum_vec = vec![(-1., p.weighted_value.clone())];
-    for (_, c) in &p.children {
-        sum_vec.push((1., c.1.clone()));
+// Estimated values of unexpanded children, by path from the root. Children without one count as a win for the
+// player whose LP is being solved, 1 in P1's and -1 in P2's, so each player is optimistic about what is unexplored
+pub trait LeafEvaluator {
+    fn leaf_value(&self, path: &[usize]) -> Option<f64>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoLeaves;
+
+impl LeafEvaluator for NoLeaves {
+    fn leaf_value(&self, _path: &[usize]) -> Option<f64> {
+        None
     }
-    for e in &p.end_corresps {
-        sum_vec.push(e.clone());
+}
+
+// As returned by evaluate_frontier
+impl LeafEvaluator for HashMap<Vec<usize>, f64> {
+    fn leaf_value(&self, path: &[usize]) -> Option<f64> {
+        self.get(path).cloned()
     }
-    for t in &p.temp_end {
-        sum_vec.push(t.clone());
+}
+
+pub fn solve<G: Game + Clone, T: GameTree<G>>(root: &mut T) {
+    solve_observed(root, &mut NoObserver)
+}
+
+pub fn solve_observed<G: Game + Clone, T: GameTree<G>>(root: &mut T, obs: &mut dyn Observer<G>) {
+    Session::new().solve_observed(root, obs)
+}
+
+pub fn solve_with_leaves<G: Game + Clone, T: GameTree<G>>(root: &mut T, leaves: &dyn LeafEvaluator) {
+    Session::new().solve_with_leaves(root, leaves, &mut NoObserver)
+}
+
+pub fn solve_abstracted<G: Game + Clone, T: GameTree<G>, A: Abstraction<G>>(root: &mut T, abstraction: A) {
+    Session::with_abstraction(abstraction).solve(root)
+}
+
+pub fn solve_refined<G: Game + Clone, T: GameTree<G>>(root: &mut T, refinement: Refinement) {
+    let mut session = Session::new();
+    session.refine(refinement);
+    session.solve(root)
+}
+
+// Which equilibrium the LPs settle on. Without one, choices the opponent makes with probability zero, and nodes a
+// player's own strategy never reaches, can be played arbitrarily badly
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Refinement {
+    #[default]
+    None,
+    // Solves each LP again once per depth of the opponent's decisions, shallowest first, keeping its value and every
+    // earlier depth's while maximizing how much worse each opponent choice does than the best at its information set,
+    // so the opponent's mistakes are punished as much as the equilibrium allows, earlier mistakes first
+    Punish,
+    // Solves the game where every choice is made with at least this probability, so every node is reached and best
+    // responded to, approaching a perfect equilibrium as it shrinks. The tremble is taken back out of the strategies
+    // written to the tree. Realization weights fall like tremble^depth, so it should stay well above the solver's
+    // tolerance at the tree's depth
+    Tremble(f64),
+}
+
+// Keeps both LPs alive while a tree grows between solves: information sets seen before keep their
+// variables and constraints, and only the payoff rows are rebuilt. No enabled backend accepts a
+// starting basis, so the backend model itself is still rebuilt from the stored rows on each solve
+pub struct Session<G: Game, A: Abstraction<G> = NoAbstraction, B: Backend = Cbc> {
+    s1: SparseSolver<B>,
+    s2: SparseSolver<B>,
+    p1: PlayerTree<SparseSolver<B>, A::Bucket1>,
+    p2: PlayerTree<SparseSolver<B>, A::Bucket2>,
+    structure: (usize, usize),
+    abstraction: A,
+    refinement: Refinement,
+}
+
+impl<G: Game + Clone> Default for Session<G> {
+    fn default() -> Self {
+        Self::new()
     }
-    s.add_constraint(&sum_vec, Ordering::Equal, 0.);
-    for (_, c) in &p.children {
-        for child in &c.0 {
-            add_leaf_constraints(s, child);
+}
+
+impl<G: Game + Clone> Session<G> {
+    pub fn new() -> Session<G> {
+        Session::with_abstraction(NoAbstraction)
+    }
+}
+
+impl<G: Game + Clone, A: Abstraction<G>> Session<G, A> {
+    pub fn with_abstraction(abstraction: A) -> Session<G, A> {
+        Session::with_backend(abstraction)
+    }
+}
+
+impl<G: Game + Clone, A: Abstraction<G>, B: Backend> Session<G, A, B> {
+    // Solves the LPs with B instead of the default backend
+    pub fn with_backend(abstraction: A) -> Session<G, A, B> {
+        let mut s1 = SparseSolver::<B>::new();
+        let mut s2 = SparseSolver::<B>::new();
+        let p1 = PlayerTree::new(&mut s1, &mut s2);
+        let p2 = PlayerTree::new(&mut s2, &mut s1);
+        s1.add_constraint(&vec![(1., p1.strategy)], Ordering::Equal, 1.);
+        s2.add_constraint(&vec![(1., p2.strategy)], Ordering::Equal, 1.);
+        let structure = (s1.num_constraints(), s2.num_constraints());
+        Session { s1, s2, p1, p2, structure, abstraction, refinement: Refinement::None }
+    }
+
+    // A tremble is part of the LPs' structure, so it can only change before the first solve
+    pub fn refine(&mut self, refinement: Refinement) {
+        let tremble = if let Refinement::Tremble(t) = refinement { t } else { 0. };
+        if tremble != self.p1.tremble {
+            assert!(self.p1.children.is_empty() && self.p2.children.is_empty(), "tremble should only change before the first solve");
+            self.p1.tremble = tremble;
+            self.p2.tremble = tremble;
+        }
+        self.refinement = refinement;
+    }
+
+    pub fn solve<T: GameTree<G>>(&mut self, root: &mut T) {
+        self.solve_observed(root, &mut NoObserver)
+    }
+
+    pub fn solve_observed<T: GameTree<G>>(&mut self, root: &mut T, obs: &mut dyn Observer<G>) {
+        self.solve_with_leaves(root, &NoLeaves, obs)
+    }
+
+    // Rebuilds both LPs for root without solving them, returning their sizes
+    pub fn build<T: GameTree<G>>(&mut self, root: &T, leaves: &dyn LeafEvaluator) -> (ModelStats, ModelStats) {
+        let Session { s1, s2, p1, p2, structure, abstraction, .. } = self;
+        s1.truncate(structure.0);
+        s2.truncate(structure.1);
+        p1.clear_payoffs();
+        p2.clear_payoffs();
+        Exploration::new(s1, s2, root, Vec::new(), leaves, &*abstraction).rec(p1, p2, Some(root.root()), 1.0, &[], &[]);
+        *structure = (s1.num_constraints(), s2.num_constraints());
+        s1.keep();
+        s2.keep();
+        add_leaf_constraints(s1, p2);
+        add_leaf_constraints(s2, p1);
+        (s1.model().stats(), s2.model().stats())
+    }
+
+    // Recall is only checked in debug builds as it walks the whole tree; call assert_perfect_recall for release
+    pub fn solve_with_leaves<T: GameTree<G>>(&mut self, root: &mut T, leaves: &dyn LeafEvaluator, obs: &mut dyn Observer<G>) {
+        if cfg!(debug_assertions) {
+            assert_perfect_recall(&*root, &self.abstraction);
+        }
+        self.build(&*root, leaves);
+        let Session { s1, s2, p1, p2, abstraction, refinement, .. } = self;
+        let sol1 = solve_lp(s1, p2, 1., 1, *refinement, obs);
+        let sol2 = solve_lp(s2, p1, -1., 2, *refinement, obs);
+        let solution = extract_solution(p1, p2, &*root, &|n| sol1[n.strategy], &|n| sol2[n.strategy], leaves, &*abstraction);
+        for (path, prob, value) in solution {
+            root.set_solution(&path, prob, value);
         }
     }
 }
 
-fn extract_solution_rec<S: Solver, G: Game + Clone>(p1: &PlayerTree<S, G::P1>, p2: &PlayerTree<S, G::P2>, maybe_node: Option<&mut Tree<G>>, msgs1: &Vec<<G::P1 as PlayerTraits>::Message>, msgs2: &Vec<<G::P2 as PlayerTraits>::Message>, sol1: &impl Fn(S::Variable) -> f64, sol2: &impl Fn(S::Variable) -> f64) {
-    match maybe_node {
-        None => {
-            return;
+// Solves the LP maximizing sign times the opponent's root value, returning every variable
+fn solve_lp<G: Game, B: Backend, K: Eq + Hash>(s: &mut SparseSolver<B>, opponent: &mut PlayerTree<SparseSolver<B>, K>, sign: f64, player: usize, refinement: Refinement, obs: &mut dyn Observer<G>) -> Vec<f64> {
+    let goal = vec![(sign, opponent.weighted_value)];
+    obs.lp_built(player, s.model(), &goal);
+    let vars = s.num_vars();
+    let (value, primal) = {
+        let sol = s.solve_consuming(goal);
+        (sol(opponent.weighted_value), (0..vars).map(&sol).collect())
+    };
+    obs.lp_solved(player, sign*value);
+    if refinement != Refinement::Punish {
+        return primal;
+    }
+    // The solve consumed the payoff rows
+    add_leaf_constraints(s, opponent);
+    keep_optimum(s, vec![(sign, opponent.weighted_value)], sign*value);
+    let mut stages = Vec::new();
+    deviations(opponent, sign, 0, &mut stages);
+    let Some(last) = stages.pop() else {
+        return primal;
+    };
+    for goal in stages {
+        let best = {
+            let sol = s.solve(goal.clone());
+            goal.iter().map(|&(c, v)| c*sol(v)).sum()
+        };
+        keep_optimum(s, goal, best);
+    }
+    let sol = s.solve_consuming(last);
+    (0..vars).map(sol).collect()
+}
+
+// Loosened by about the backend's tolerance, so the optimum found stays feasible
+fn keep_optimum<S: Solver>(s: &mut S, goal: Vec<(f64, S::Variable)>, best: f64) {
+    s.add_constraint(&goal, Ordering::Greater, best - 1e-7*best.abs().max(1.));
+}
+
+// By depth of the opponent's information sets, the sum over their choices of how much worse each does for the
+// opponent than the information set's value. A sequence is only weighed at the information set it starts at, and
+// its value already includes what it is worth deeper down
+fn deviations<S: Solver, K: Eq + Hash>(p: &PlayerTree<S, K>, sign: f64, depth: usize, stages: &mut Vec<Vec<(f64, S::Variable)>>) {
+    for (children, value) in p.children.values() {
+        if stages.len() == depth {
+            stages.push(Vec::new());
+        }
+        stages[depth].push((-sign*children.len() as f64, value.clone()));
+        for child in children {
+            stages[depth].push((sign, child.weighted_value.clone()));
+            deviations(child, sign, depth + 1, stages);
         }
-        Some(node) => {
-            let prob: Vec<f64>;
-            let mut value = 0.;
-            match node.node_type.clone() {
+    }
+}
+
+// B has to report duals, which among the backends only Clarabel does, so this needs the clarabel feature:
+// solve_single::<Clarabel, _, _>(&mut tree)
+pub fn solve_single<B: DualBackend, G: Game + Clone, T: GameTree<G>>(root: &mut T) {
+    solve_single_observed::<B, G, T>(root, &mut NoObserver)
+}
+
+// Solves only P1's LP; the duals of its value constraints are P2's realization weights. Like Session, checks recall
+// only in debug builds
+pub fn solve_single_observed<B: DualBackend, G: Game + Clone, T: GameTree<G>>(root: &mut T, obs: &mut dyn Observer<G>) {
+    if cfg!(debug_assertions) {
+        assert_perfect_recall(&*root, &NoAbstraction);
+    }
+    let mut s1 = SparseSolver::<B>::new();
+    // only collects P2's strategy and P1's value variables, it is never solved
+    let mut s2 = SparseSolver::<B>::new();
+    let mut p1 = PlayerTree::new(&mut s1, &mut s2);
+    let mut p2 = PlayerTree::new(&mut s2, &mut s1);
+    Exploration::new(&mut s1, &mut s2, &*root, Vec::new(), &NoLeaves, &NoAbstraction).rec(&mut p1, &mut p2, Some(root.root()), 1.0, &[], &[]);
+    s1.add_constraint(&vec![(1., p1.strategy)], Ordering::Equal, 1.);
+    add_leaf_constraints(&mut s1, &mut p2);
+    let goal = vec![(1., p2.weighted_value)];
+    obs.lp_built(1, s1.model(), &goal);
+    let (sol, dual) = s1.solve_with_dual(goal);
+    obs.lp_solved(1, sol(p2.weighted_value));
+    let solution = extract_solution(&p1, &p2, &*root, &|n| sol(n.strategy), &|n| dual(n.leaf.expect("value constraints should be added")).abs(), &NoLeaves, &NoAbstraction);
+    for (path, prob, value) in solution {
+        root.set_solution(&path, prob, value);
+    }
+}
+
+// Walks the game tree below a node, growing both players' PlayerTrees and collecting the payoffs at their ends. path
+// is the current node's
+pub(super) struct Exploration<'a, S: Solver, G: Game, T: GameTree<G>, A: Abstraction<G>> {
+    s1: &'a mut S,
+    s2: &'a mut S,
+    tree: &'a T,
+    path: Vec<usize>,
+    leaves: &'a dyn LeafEvaluator,
+    abstraction: &'a A,
+    game_type: PhantomData<G>,
+}
+
+impl<'a, S: Solver, G: Game + Clone + 'a, T: GameTree<G>, A: Abstraction<G>> Exploration<'a, S, G, T, A> {
+    pub(super) fn new(s1: &'a mut S, s2: &'a mut S, tree: &'a T, path: Vec<usize>, leaves: &'a dyn LeafEvaluator, abstraction: &'a A) -> Self {
+        Exploration { s1, s2, tree, path, leaves, abstraction, game_type: PhantomData }
+    }
+
+    // nature is the chance reach, and msgs1 and msgs2 what each player was told so far
+    pub(super) fn rec(&mut self, p1: &mut PlayerTree<S, A::Bucket1>, p2: &mut PlayerTree<S, A::Bucket2>, maybe_node: Option<T::Node<'a>>, nature: f64, msgs1: &[<G::P1 as PlayerTraits>::Message], msgs2: &[<G::P2 as PlayerTraits>::Message]) {
+        let tree = self.tree;
+        match maybe_node {
+            None => match self.leaves.leaf_value(&self.path) {
+                Some(v) => {
+                    p1.end_corresps.push((nature*v, p2.strategy.clone()));
+                    p2.end_corresps.push((nature*v, p1.strategy.clone()));
+                }
+                None => {
+                    p1.temp_end.push((-nature, p2.strategy.clone()));
+                    p2.temp_end.push((nature, p1.strategy.clone()));
+                }
+            }
+            Some(node) => match tree.node_type(node) {
                 NodeType::Message1(m) => {
-                    prob = vec![(1.)];
-                    let mut next_msgs1 = msgs1.clone();
-                    next_msgs1.push(m);
-                    extract_solution_rec(p1, p2, node.children[0].as_mut(), &next_msgs1, msgs2, sol1, sol2);
+                    self.path.push(0);
+                    self.rec(p1, p2, tree.child(node, 0), nature, &[msgs1, std::slice::from_ref(m)].concat(), msgs2);
+                    self.path.pop();
                 }
                 NodeType::Message2(m) => {
-                    prob = vec![(1.)];
-                    let mut next_msgs2 = msgs2.clone();
-                    next_msgs2.push(m);
-                    extract_solution_rec(p1, p2, node.children[0].as_mut(), msgs1, &next_msgs2, sol1, sol2);
+                    self.path.push(0);
+                    self.rec(p1, p2, tree.child(node, 0), nature, msgs1, &[msgs2, std::slice::from_ref(m)].concat());
+                    self.path.pop();
                 }
                 NodeType::Player1(c) => {
-                    let children = &p1.children.get(&(msgs1.clone(), c.len())).expect("tree should be fully explored").0;
-                    let root_prob = sol1(p1.strategy.clone());
-                    prob = children.iter().map(|n| sol1(n.strategy.clone())/root_prob).collect();
-                    if root_prob > EPS {
-                        for i in 0..c.len() {
-                            extract_solution_rec(&children[i], p2, node.children[i].as_mut(), &Vec::new(), msgs2, sol1, sol2);
-                        }
+                    let children = &mut p1.get_children(self.abstraction.bucket1(msgs1), c.len(), self.s1, self.s2, 1.).0;
+                    for (i, child) in children.iter_mut().enumerate() {
+                        self.path.push(i);
+                        self.rec(child, p2, tree.child(node, i), nature, msgs1, msgs2);
+                        self.path.pop();
                     }
                 }
                 NodeType::Player2(c) => {
-                    let children = &p2.children.get(&(msgs2.clone(), c.len())).expect("tree should be fully explored").0;
-                    let root_prob = sol2(p2.strategy.clone());
-                    prob = children.iter().map(|n| sol2(n.strategy.clone())/root_prob).collect();
-                    if root_prob > EPS {
-                        for i in 0..c.len() {
-                            extract_solution_rec(p1, &children[i], node.children[i].as_mut(), msgs1, &Vec::new(), sol1, sol2);
-                        }
+                    let children = &mut p2.get_children(self.abstraction.bucket2(msgs2), c.len(), self.s2, self.s1, -1.).0;
+                    for (i, child) in children.iter_mut().enumerate() {
+                        self.path.push(i);
+                        self.rec(p1, child, tree.child(node, i), nature, msgs1, msgs2);
+                        self.path.pop();
                     }
                 }
-                NodeType::Random(r) => {
-                    prob = node.prob.clone().expect("random nodes should always have prob");
-                    for i in 0..r.len() {
-                        extract_solution_rec(p1, p2, node.children[i].as_mut(), msgs1, msgs2, sol1, sol2);
+                NodeType::Random(_) => {
+                    let prob = tree.prob(node).expect("random nodes should have prob");
+                    for (i, p) in prob.iter().enumerate() {
+                        self.path.push(i);
+                        self.rec(p1, p2, tree.child(node, i), nature*p, msgs1, msgs2);
+                        self.path.pop();
                     }
                 }
                 NodeType::End => {
-                    prob = vec![];
-                    value = node.value.expect("end nodes should always have value");
-                }
+                    p1.end_corresps.push((nature*tree.value(node).expect("end nodes should have value"), p2.strategy.clone()));
+                    p2.end_corresps.push((nature*tree.value(node).expect("end nodes should have value"), p1.strategy.clone()));
+                },
             }
-            for i in 0..node.children.len() {
-                if let Some(v) = node.children[i].as_ref().and_then(|n| n.value) {
-                    if prob[i] > EPS {
-                        value += v*prob[i]
-                    }
-                }
+        };
+    }
+}
+
+pub(super) fn add_leaf_constraints<S: Solver, K: Eq + Hash>(s: &mut S, p: &mut PlayerTree<S, K>) {
+    let mut sum_vec = vec![(-1., p.weighted_value.clone())];
+    for (_, v) in p.children.values() {
+        sum_vec.push((1., v.clone()));
+    }
+    for e in &p.end_corresps {
+        sum_vec.push(e.clone());
+    }
+    for t in &p.temp_end {
+        sum_vec.push(t.clone());
+    }
+    p.leaf = Some(s.add_constraint(&sum_vec, Ordering::Equal, 0.));
+    for c in p.children.values_mut() {
+        for child in &mut c.0 {
+            add_leaf_constraints(s, child);
+        }
+    }
+}
+
+// Realization weight of a sequence in the solved LP
+type StrategyFn<'a, S, K> = dyn Fn(&PlayerTree<S, K>) -> f64 + 'a;
+
+// (path, prob, value) for every node of tree
+fn extract_solution<S: Solver, G: Game + Clone, T: GameTree<G>, A: Abstraction<G>>(p1: &PlayerTree<S, A::Bucket1>, p2: &PlayerTree<S, A::Bucket2>, tree: &T, strat1: &StrategyFn<'_, S, A::Bucket1>, strat2: &StrategyFn<'_, S, A::Bucket2>, leaves: &dyn LeafEvaluator, abstraction: &A) -> Vec<(Vec<usize>, Vec<f64>, f64)> {
+    let mut extraction = Extraction { tree, strat1, strat2, leaves, abstraction, path: Vec::new(), solution: Vec::new(), game_type: PhantomData };
+    extraction.rec(p1, p2, tree.root(), &[], &[], (1., 1.));
+    extraction.solution
+}
+
+struct Extraction<'a, S: Solver, G: Game, T: GameTree<G>, A: Abstraction<G>> {
+    tree: &'a T,
+    strat1: &'a StrategyFn<'a, S, A::Bucket1>,
+    strat2: &'a StrategyFn<'a, S, A::Bucket2>,
+    leaves: &'a dyn LeafEvaluator,
+    abstraction: &'a A,
+    path: Vec<usize>,
+    solution: Vec<(Vec<usize>, Vec<f64>, f64)>,
+    game_type: PhantomData<G>,
+}
+
+impl<'a, S: Solver, G: Game + Clone + 'a, T: GameTree<G>, A: Abstraction<G>> Extraction<'a, S, G, T, A> {
+    // Solves the whole subtree at node and returns its value. Information sets a player's own strategy never reaches
+    // are still solved, with uniform play. floors are the realization weights each player's trembles alone guarantee
+    // here, which the threshold for being reached scales with
+    fn rec(&mut self, p1: &PlayerTree<S, A::Bucket1>, p2: &PlayerTree<S, A::Bucket2>, node: T::Node<'a>, msgs1: &[<G::P1 as PlayerTraits>::Message], msgs2: &[<G::P2 as PlayerTraits>::Message], floors: (f64, f64)) -> f64 {
+        let tree = self.tree;
+        let (prob, values): (Vec<f64>, Vec<Option<f64>>) = match tree.node_type(node) {
+            NodeType::Message1(m) => {
+                let next_msgs1 = [msgs1, std::slice::from_ref(m)].concat();
+                (vec![1.], vec![self.child(node, 0, |e, c| e.rec(p1, p2, c, &next_msgs1, msgs2, floors))])
+            }
+            NodeType::Message2(m) => {
+                let next_msgs2 = [msgs2, std::slice::from_ref(m)].concat();
+                (vec![1.], vec![self.child(node, 0, |e, c| e.rec(p1, p2, c, msgs1, &next_msgs2, floors))])
+            }
+            NodeType::Player1(c) => {
+                let children = &p1.children.get(&(self.abstraction.bucket1(msgs1), c.len())).expect("tree should be fully explored").0;
+                let prob = behavioral(children.iter().map(self.strat1), (self.strat1)(p1), p1.tremble, floors.0);
+                let floors = (floors.0*tremble_factor(p1.tremble), floors.1);
+                (prob, children.iter().enumerate().map(|(i, child)| self.child(node, i, |e, c| e.rec(child, p2, c, msgs1, msgs2, floors))).collect())
+            }
+            NodeType::Player2(c) => {
+                let children = &p2.children.get(&(self.abstraction.bucket2(msgs2), c.len())).expect("tree should be fully explored").0;
+                let prob = behavioral(children.iter().map(self.strat2), (self.strat2)(p2), p2.tremble, floors.1);
+                let floors = (floors.0, floors.1*tremble_factor(p2.tremble));
+                (prob, children.iter().enumerate().map(|(i, child)| self.child(node, i, |e, c| e.rec(p1, child, c, msgs1, msgs2, floors))).collect())
+            }
+            NodeType::Random(r) => {
+                let prob = tree.prob(node).expect("random nodes should always have prob").to_vec();
+                (prob, (0..r.len()).map(|i| self.child(node, i, |e, c| e.rec(p1, p2, c, msgs1, msgs2, floors))).collect())
+            }
+            NodeType::End => (vec![], vec![]),
+        };
+        let value = match tree.node_type(node) {
+            NodeType::End => tree.value(node).expect("end nodes should always have value"),
+            _ => prob.iter().zip(&values).filter_map(|(p, v)| v.map(|v| p*v)).sum(),
+        };
+        self.solution.push((self.path.clone(), prob, value));
+        value
+    }
+
+    // Solves child i of node, or asks the leaf evaluator if it isn't expanded
+    fn child(&mut self, node: T::Node<'a>, i: usize, solve: impl FnOnce(&mut Self, T::Node<'a>) -> f64) -> Option<f64> {
+        self.path.push(i);
+        let value = match self.tree.child(node, i) {
+            Some(c) => Some(solve(self, c)),
+            None => self.leaves.leaf_value(&self.path),
+        };
+        self.path.pop();
+        value
+    }
+}
+
+// How much one choice shrinks the realization weight a tremble guarantees
+fn tremble_factor(tremble: f64) -> f64 {
+    if tremble > 0. { tremble } else { 1. }
+}
+
+// Choice probabilities from realization weights with the tremble taken out, uniform where the player's own strategy
+// never gets there. Reached weights can't fall below floor, so the threshold is relative to it
+fn behavioral(weights: impl ExactSizeIterator<Item = f64>, root_prob: f64, tremble: f64, floor: f64) -> Vec<f64> {
+    let n = weights.len();
+    if root_prob > EPS*floor {
+        weights.map(|w| ((w/root_prob - tremble)/(1. - n as f64*tremble)).max(0.)).collect()
+    } else {
+        vec_of_repeat(n, 1./n as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tic_tac_toe::TicTacToe;
+
+    struct Script(Vec<usize>);
+
+    impl<T: PlayerTraits> Player<T> for Script {
+        fn receive_message(&mut self, _: &T::Message) {}
+        fn choose(&mut self, _: &Vec<T::Choice>) -> usize {
+            self.0.remove(0)
+        }
+    }
+
+    // X X .
+    // . O .
+    // . O X
+    // O to move. Blocking at the top right draws, anything else lets X win
+    fn blunder_position() -> Tree<TicTacToe> {
+        let mut game = TicTacToe::new();
+        let mut g = DefaultGameInterface {
+            game_type: PhantomData,
+            randomer: |_: &Vec<f64>, _: &Vec<_>| 0,
+            player1: Script(vec![0, 0, 4]),
+            player2: Script(vec![3, 4]),
+            ender: |_| panic!("position should not be over"),
+        };
+        for _ in 0..5 {
+            game.step(&mut g).expect("position should not be over");
+        }
+        let mut tree = Tree::new_root(game);
+        expand_full(&mut tree);
+        tree
+    }
+
+    fn all_solved(t: &Tree<TicTacToe>) -> bool {
+        t.prob().is_some() && t.value().is_some() && (0..t.num_children()).all(|i| all_solved(t.child(i).unwrap()))
+    }
+
+    #[test]
+    fn solves_subtrees_off_the_equilibrium_path() {
+        let mut tree = blunder_position();
+        solve(&mut tree);
+        assert!(all_solved(&tree));
+        assert!(tree.value().unwrap().abs() < 1e-6);
+    }
+
+    fn punishes_a_blunder<B: Backend>() {
+        for refinement in [Refinement::Punish, Refinement::Tremble(1e-3)] {
+            let mut tree = blunder_position();
+            let mut session = Session::<_, NoAbstraction, B>::with_backend(NoAbstraction);
+            session.refine(refinement);
+            session.solve(&mut tree);
+            assert!(tree.value().unwrap().abs() < 1e-6, "{refinement:?}");
+            for blunder in 1..4 {
+                let reply = tree.child(blunder).unwrap().child(0).unwrap();
+                assert!((reply.value().unwrap() - 1.).abs() < 1e-6, "{refinement:?} should win after blunder {blunder}");
             }
-            node.prob = Some(prob);
-            node.value = Some(value);
         }
     }
-}
\ No newline at end of file
+
+    // A simplex lands on a vertex and an interior point method between them, so the refinements should leave no ties
+    // that decide the answer
+    #[test]
+    fn refinements_punish_a_blunder() {
+        punishes_a_blunder::<Cbc>();
+        #[cfg(feature = "clarabel")]
+        punishes_a_blunder::<crate::common::lp_solver::Clarabel>();
+    }
+
+    #[cfg(feature = "clarabel")]
+    #[test]
+    fn single_lp_matches_both() {
+        use crate::common::lp_solver::Clarabel;
+        use crate::game::rps::RPS;
+        let both = Tree::new(RPS::new());
+        let mut single = Tree::new_root(RPS::new());
+        expand_full(&mut single);
+        solve_single::<Clarabel, _, _>(&mut single);
+        assert!((both.value().unwrap() - single.value().unwrap()).abs() < 1e-6);
+        for (p, q) in both.child(0).unwrap().prob().unwrap().iter().zip(single.child(0).unwrap().prob().unwrap()) {
+            assert!((p - q).abs() < 1e-6, "P2's strategy should come from the duals");
+        }
+    }
+}
@@ -1,118 +1,333 @@
 use super::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::collections::HashMap;
 
+// The choices along a path, handed out one at a time while a game replays them. Running out marks the node to record
 #[derive(Debug)]
-struct MockInterface<G: Game + Clone> {
-    root: G,
-    path: Vec<usize>,
+pub(super) struct Replay<'a> {
+    path: &'a [usize],
     i: usize,
+}
+
+impl<'a> Replay<'a> {
+    pub(super) fn new(path: &'a [usize]) -> Replay<'a> {
+        Replay { path, i: 0 }
+    }
+
+    pub(super) fn position(&self) -> usize {
+        self.i
+    }
+
+    pub(super) fn choice(&mut self) -> Option<usize> {
+        let choice = self.path.get(self.i).cloned();
+        if choice.is_some() {
+            self.i += 1;
+        }
+        choice
+    }
+
+    pub(super) fn message(&mut self) -> Option<()> {
+        let choice = self.choice()?;
+        assert_eq!(0, choice, "message should only have one choice {:?}", self);
+        Some(())
+    }
+
+    pub(super) fn end(&self) {
+        assert_eq!(self.path.len(), self.i, "no choices should happen after end {:?}", self);
+    }
+}
+
+// Steps a copy of root, with interface replaying a path, until it stops where the path runs out. Returns the state
+// saved at the start of that last step and the position in the path where the step starts
+pub(super) fn replay_steps<G: Clone, I>(mut root: Arc<G>, interface: &mut I, step: impl Fn(&mut G, &mut I) -> Option<()>, position: impl Fn(&I) -> usize) -> (Arc<G>, usize) {
+    let mut game = G::clone(&root);
+    let mut start = 0;
+    while step(&mut game, interface).is_some() {
+        root = Arc::new(game.clone());
+        start = position(interface);
+    }
+    (root, start)
+}
+
+#[derive(Debug)]
+struct MockInterface<'a, G: Game + Clone> {
+    snapshot: &'a Arc<Snapshot<G>>,
+    replay: Replay<'a>,
     recorded: Option<Tree<G>>,
 }
 
-impl<G: Game + Clone + Debug> GameInterface<G> for MockInterface<G> {
+impl<G: Game + Clone + Debug> MockInterface<'_, G> {
+    fn record(&mut self, node_type: NodeType<G>, n: usize) -> &mut Tree<G> {
+        assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
+        self.recorded.insert(Tree::new_node(node_type, self.snapshot.clone(), Vec::new(), n))
+    }
+}
+
+impl<G: Game + Clone + Debug> GameInterface<G> for MockInterface<'_, G> {
     fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize> {
-        let n = v.len();
-        if let Some(choice) = self.path.get(self.i) {
-            self.i += 1;
-            Some(*choice)
-        } else {
-            assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-            let node_type = NodeType::Random(v.clone());
-            let mut new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), n);
-            new_node.prob = Some(p.clone());
-            self.recorded = Some(new_node);
+        self.replay.choice().or_else(|| {
+            self.record(NodeType::Random(v.clone()), v.len()).prob = Some(p.clone());
             None
-        }
+        })
     }
     fn p1_choice(&mut self, v: &Vec<<G::P1 as PlayerTraits>::Choice>) -> Option<usize> {
-        let n = v.len();
-        if let Some(choice) = self.path.get(self.i) {
-            self.i += 1;
-            Some(*choice)
-        } else {
-            assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-            let node_type = NodeType::Player1(v.clone());
-            let new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), n);
-            self.recorded = Some(new_node);
+        self.replay.choice().or_else(|| {
+            self.record(NodeType::Player1(v.clone()), v.len());
             None
-        }
+        })
     }
     fn p2_choice(&mut self, v: &Vec<<G::P2 as PlayerTraits>::Choice>) -> Option<usize> {
-        let n = v.len();
-        if let Some(choice) = self.path.get(self.i) {
-            self.i += 1;
-            Some(*choice)
-        } else {
-            assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-            let node_type = NodeType::Player2(v.clone());
-            let new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), n);
-            self.recorded = Some(new_node);
+        self.replay.choice().or_else(|| {
+            self.record(NodeType::Player2(v.clone()), v.len());
             None
-        }
+        })
     }
     fn p1_message(&mut self, msg: &<G::P1 as PlayerTraits>::Message) -> Option<()> {
-        if let Some(choice) = self.path.get(self.i) {
-            self.i += 1;
-            assert_eq!(0, *choice, "message should only have one choice {:?}", self);
-            Some(())
-        } else {
-            assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-            let node_type = NodeType::Message1(msg.clone());
-            let new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), 1);
-            self.recorded = Some(new_node);
+        self.replay.message().or_else(|| {
+            self.record(NodeType::Message1(msg.clone()), 1);
             None
-        }
+        })
     }
     fn p2_message(&mut self, msg: &<G::P2 as PlayerTraits>::Message) -> Option<()> {
-        if let Some(choice) = self.path.get(self.i) {
-            self.i += 1;
-            assert_eq!(0, *choice, "message should only have one choice {:?}", self);
-            Some(())
-        } else {
-            assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-            let node_type = NodeType::Message2(msg.clone());
-            let new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), 1);
-            self.recorded = Some(new_node);
+        self.replay.message().or_else(|| {
+            self.record(NodeType::Message2(msg.clone()), 1);
             None
-        }
+        })
     }
     fn end(&mut self, value: f64) {
-        assert_eq!(self.path.len(), self.i, "no choices should happen after end {:?}", self);
-        assert!(self.recorded.is_none(), "recording should happen exactly once {:?}", self);
-        let node_type = NodeType::End;
-        let mut new_node = Tree::new_node(node_type, self.root.clone(), self.path.clone(), 0);
-        new_node.value = Some(value);
-        self.recorded = Some(new_node);
+        self.replay.end();
+        self.record(NodeType::End, 0).value = Some(value);
     }
 }
 
-pub fn make_node<G: Game + Clone + Debug>(mut root: G, path: Vec<usize>) -> Tree<G> {
-    let mut mock = MockInterface {
-        root: root.clone(),
-        path: path,
-        i: 0,
-        recorded: None,
-    };
-    let mut game = root.clone();
-    let mut i = 0;
-    while  game.step(&mut mock).is_some() {
-        root = game.clone();
-        i = mock.i;
+// Choices made past a snapshot before nodes save a new one, which bounds the replay when a node is expanded on its own
+const SNAPSHOT_INTERVAL: usize = 16;
+
+// The game state at the start of a node's step and the node's choices within that step. It is only kept while
+// expanding, never in the tree, so each child costs one step from here instead of a replay from the snapshot
+#[derive(Debug)]
+pub(super) struct Live<G> {
+    pub(super) start: Arc<G>,
+    pub(super) local: Vec<usize>,
+}
+
+// Steps a copy of start, replaying path, until the node where it runs out is recorded. The node's path is left for
+// the caller to fill in
+fn record<G: Game + Clone + Debug>(snapshot: &Arc<Snapshot<G>>, start: Arc<G>, path: &[usize]) -> (Tree<G>, Live<G>) {
+    let mut mock = MockInterface { snapshot, replay: Replay::new(path), recorded: None };
+    let (start, position) = replay_steps(start, &mut mock, |game, mock| game.step(mock), |mock| mock.replay.position());
+    let node = mock.recorded.expect("recording should happen exactly once");
+    (node, Live { start, local: path[position..].to_vec() })
+}
+
+// Shares snapshot unless the node starts a step far enough past it, where a new snapshot is saved instead
+fn place<G: Clone>(snapshot: &Arc<Snapshot<G>>, suffix: Vec<usize>, live: &Live<G>) -> NodePath<G> {
+    if live.local.is_empty() && suffix.len() >= SNAPSHOT_INTERVAL {
+        let path = [&snapshot.path[..], &suffix[..]].concat();
+        (Arc::new(Snapshot { game: G::clone(&live.start), path }), Vec::new())
+    } else {
+        (snapshot.clone(), suffix)
     }
-    let mut ret = mock.recorded.expect("recording should happen exactly once");
-    ret.path = (root, mock.path.split_at(i).1.to_vec());
-    ret
+}
+
+// Replays path from the snapshot, for nodes expanded without their parent's live state
+pub fn make_node<G: Game + Clone + Debug>(snapshot: Arc<Snapshot<G>>, path: Vec<usize>) -> Tree<G> {
+    let (mut node, live) = record(&snapshot, Arc::new(snapshot.game.clone()), &path);
+    node.path = place(&snapshot, path, &live);
+    node
+}
+
+// The live state of a node already in the tree, replayed once from its snapshot
+pub(super) fn resume<G: Game + Clone + Debug>(path: &NodePath<G>) -> Live<G> {
+    record(&path.0, Arc::new(path.0.game.clone()), &path.1).1
+}
+
+// Child i of the node at path, stepping on from the node's live state
+pub(super) fn make_child<G: Game + Clone + Debug>(path: &NodePath<G>, live: &Live<G>, i: usize) -> (Tree<G>, Live<G>) {
+    let local = [&live.local[..], &[i]].concat();
+    let (mut child, child_live) = record(&path.0, live.start.clone(), &local);
+    child.path = place(&path.0, [&path.1[..], &[i]].concat(), &child_live);
+    (child, child_live)
 }
 
 pub fn expand<G: Game + Clone + Debug>(node: &mut Tree<G>, child: usize) {
+    expand_observed(node, child, &mut NoObserver)
+}
+
+pub fn expand_observed<G: Game + Clone + Debug>(node: &mut Tree<G>, child: usize, obs: &mut dyn Observer<G>) {
     assert!(node.children[child].is_none(), "should not expand already expanded child");
     let mut child_path = node.path.1.clone();
     child_path.push(child);
-    node.children[child] = Some(make_node(node.path.0.clone(), child_path));
+    let new_node = make_node(node.path.0.clone(), child_path);
+    obs.expand(&new_node);
+    node.children[child] = Some(new_node);
 }
 
 pub fn expand_full<G: Game + Clone + Debug>(node: &mut Tree<G>) {
+    expand_full_observed(node, &mut NoObserver)
+}
+
+pub fn expand_full_observed<G: Game + Clone + Debug>(node: &mut Tree<G>, obs: &mut dyn Observer<G>) {
+    let live = resume(&node.path);
+    expand_full_rec(node, &live, obs)
+}
+
+fn expand_full_rec<G: Game + Clone + Debug>(node: &mut Tree<G>, live: &Live<G>, obs: &mut dyn Observer<G>) {
+    for i in 0..node.children.len() {
+        let child_live = match &node.children[i] {
+            Some(child) => resume(&child.path),
+            None => {
+                let (child, child_live) = make_child(&node.path, live, i);
+                obs.expand(&child);
+                node.children[i] = Some(child);
+                child_live
+            }
+        };
+        expand_full_rec(node.children[i].as_mut().expect("child should be expanded after make_child()"), &child_live, obs)
+    }
+}
+#[cfg(feature = "parallel")]
+pub fn expand_full_par<G: Game + Clone + Debug + Send + Sync>(node: &mut Tree<G>) where Tree<G>: Send {
+    let live = resume(&node.path);
+    expand_full_par_rec(node, &live)
+}
+
+#[cfg(feature = "parallel")]
+fn expand_full_par_rec<G: Game + Clone + Debug + Send + Sync>(node: &mut Tree<G>, live: &Live<G>) where Tree<G>: Send {
+    use rayon::prelude::*;
+    let path = &node.path;
+    node.children.par_iter_mut().enumerate().for_each(|(i, child)| {
+        let child_live = match child {
+            Some(child) => resume(&child.path),
+            None => {
+                let (new_child, child_live) = make_child(path, live, i);
+                *child = Some(new_child);
+                child_live
+            }
+        };
+        expand_full_par_rec(child.as_mut().expect("child should be expanded after make_child()"), &child_live);
+    });
+}
+
+// Replays path, then plays uniformly random choices (and random events by their probabilities) until the end
+struct RolloutInterface<'a, R: Rng> {
+    path: &'a [usize],
+    i: usize,
+    rng: &'a mut R,
+    value: Option<f64>,
+}
+
+impl<'a, R: Rng> RolloutInterface<'a, R> {
+    fn next(&mut self, n: usize) -> usize {
+        let choice = self.path.get(self.i).cloned().unwrap_or_else(|| self.rng.gen_range(0..n));
+        self.i += 1;
+        choice
+    }
+}
+
+impl<'a, G: Game, R: Rng> GameInterface<G> for RolloutInterface<'a, R> {
+    fn random(&mut self, p: &Vec<f64>, _: &Vec<G::RandomChoice>) -> Option<usize> {
+        if let Some(choice) = self.path.get(self.i) {
+            self.i += 1;
+            return Some(*choice);
+        }
+        Some(WeightedIndex::new(p).expect("random events should have a valid distribution").sample(self.rng))
+    }
+    fn p1_choice(&mut self, v: &Vec<<G::P1 as PlayerTraits>::Choice>) -> Option<usize> {
+        Some(self.next(v.len()))
+    }
+    fn p2_choice(&mut self, v: &Vec<<G::P2 as PlayerTraits>::Choice>) -> Option<usize> {
+        Some(self.next(v.len()))
+    }
+    fn p1_message(&mut self, _: &<G::P1 as PlayerTraits>::Message) -> Option<()> {
+        self.next(1);
+        Some(())
+    }
+    fn p2_message(&mut self, _: &<G::P2 as PlayerTraits>::Message) -> Option<()> {
+        self.next(1);
+        Some(())
+    }
+    fn end(&mut self, value: f64) {
+        self.value = Some(value);
+    }
+}
+
+// Plays a random game from the state saved at snapshot, after replaying the choices in path
+pub fn rollout<G: Game + Clone, R: Rng>(snapshot: &G, path: &[usize], rng: &mut R) -> f64 {
+    let mut game = snapshot.clone();
+    let mut gi = RolloutInterface { path, i: 0, rng, value: None };
+    run_game(&mut game, &mut gi);
+    gi.value.expect("rollout should reach the end of the game")
+}
+
+// Every unexpanded child, as (snapshot, path past the snapshot, path from the root)
+fn frontier<G: Game>(node: &Tree<G>, path: &mut Vec<usize>, ret: &mut Vec<(NodePath<G>, Vec<usize>)>) {
     for i in 0..node.children.len() {
-        expand(node, i);
-        expand_full(node.children[i].as_mut().expect("child should be expanded after expand()"))
+        path.push(i);
+        match &node.children[i] {
+            Some(child) => frontier(child, path, ret),
+            None => {
+                let mut suffix = node.path.1.clone();
+                suffix.push(i);
+                ret.push(((node.path.0.clone(), suffix), path.clone()));
+            }
+        }
+        path.pop();
+    }
+}
+
+// Estimates the value of every unexpanded child as the mean of random rollouts, keyed by its path from the root
+pub fn evaluate_frontier<G: Game + Clone>(tree: &Tree<G>, rollouts: usize) -> HashMap<Vec<usize>, f64> {
+    let mut slots = Vec::new();
+    frontier(tree, &mut Vec::new(), &mut slots);
+    let mut rng = rand::thread_rng();
+    slots.into_iter().map(|((snapshot, suffix), path)| {
+        let total: f64 = (0..rollouts).map(|_| rollout(&snapshot.game, &suffix, &mut rng)).sum();
+        (path, total / rollouts as f64)
+    }).collect()
+}
+
+#[cfg(feature = "parallel")]
+pub fn evaluate_frontier_par<G: Game + Clone + Send + Sync>(tree: &Tree<G>, rollouts: usize) -> HashMap<Vec<usize>, f64> {
+    use rayon::prelude::*;
+    let mut slots = Vec::new();
+    frontier(tree, &mut Vec::new(), &mut slots);
+    slots.into_par_iter().map(|((snapshot, suffix), path)| {
+        let total: f64 = (0..rollouts).into_par_iter()
+            .map_init(rand::thread_rng, |rng, _| rollout(&snapshot.game, &suffix, rng))
+            .sum();
+        (path, total / rollouts as f64)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn rollouts_score_the_frontier() {
+        let mut tree = Tree::new_root(RPS::new());
+        expand(&mut tree, 0);
+        let values = evaluate_frontier(&tree, 100);
+        assert_eq!(5, values.len());
+        // Below P1's first choice every rollout ends where its path does
+        assert_eq!((0., -1., 1.), (values[&vec![0, 0]], values[&vec![0, 1]], values[&vec![0, 2]]));
+        assert!(values[&vec![1]].abs() <= 1. && values[&vec![2]].abs() <= 1.);
+        #[cfg(feature = "parallel")]
+        assert_eq!(values[&vec![0, 2]], evaluate_frontier_par(&tree, 100)[&vec![0, 2]]);
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_expansion_builds_the_same_tree() {
+        let mut serial = Tree::new_root(RPS::new());
+        expand_full(&mut serial);
+        let mut parallel = Tree::new_root(RPS::new());
+        expand(&mut parallel, 1);
+        expand_full_par(&mut parallel);
+        assert_eq!(tree_stats(&serial), tree_stats(&parallel));
+        assert!(evaluate_frontier(&parallel, 1).is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use super::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Observation {
+    Message(String),
+    Choice(String),
+}
+
+impl Display for Observation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Observation::Message(m) => write!(f, "{m}"),
+            Observation::Choice(c) => write!(f, "-> {c}"),
+        }
+    }
+}
+
+// Everything a player has seen when making a choice, rendered through Display so it can be used as a lookup key
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoSet {
+    pub player: usize,
+    pub history: Vec<Observation>,
+    pub choices: Vec<String>,
+}
+
+impl InfoSet {
+    pub fn new<T: Display>(player: usize, history: &[Observation], choices: &[T]) -> InfoSet {
+        InfoSet {
+            player,
+            history: history.to_vec(),
+            choices: choices.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+impl Display for InfoSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "P{}", self.player)?;
+        for o in &self.history {
+            write!(f, " | {o}")?;
+        }
+        write!(f, " | [{}]", self.choices.join(", "))
+    }
+}
+
+// Both players' observations along a path from the root
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Histories {
+    pub p1: Vec<Observation>,
+    pub p2: Vec<Observation>,
+}
+
+impl Histories {
+    pub fn info_set<G: Game>(&self, node_type: &NodeType<G>) -> Option<InfoSet> {
+        match node_type {
+            NodeType::Player1(c) => Some(InfoSet::new(1, &self.p1, c)),
+            NodeType::Player2(c) => Some(InfoSet::new(2, &self.p2, c)),
+            _ => None,
+        }
+    }
+
+    pub fn child<G: Game>(&self, node_type: &NodeType<G>, i: usize) -> Histories {
+        let mut ret = self.clone();
+        match node_type {
+            NodeType::Message1(m) => ret.p1.push(Observation::Message(m.to_string())),
+            NodeType::Message2(m) => ret.p2.push(Observation::Message(m.to_string())),
+            NodeType::Player1(c) => ret.p1.push(Observation::Choice(c[i].to_string())),
+            NodeType::Player2(c) => ret.p2.push(Observation::Choice(c[i].to_string())),
+            NodeType::Random(_) | NodeType::End => {}
+        }
+        ret
+    }
+}
+
+// Each of a player's information sets, by its Display, with the nodes in it that a best response has to weigh
+pub(super) type InfoSetNodes<N> = HashMap<String, (InfoSet, Vec<N>)>;
+
+// A pure best response, as the chosen action by information set. Information sets are decided deepest first, so every
+// choice below one is already fixed when its actions are compared; score gives an action's value summed over the
+// nodes, given the choices made so far
+pub(super) fn decide_deepest_first<N>(sets: &InfoSetNodes<N>, score: impl Fn(&InfoSet, &[N], usize, &HashMap<String, usize>) -> f64) -> HashMap<String, usize> {
+    let mut order: Vec<&(InfoSet, Vec<N>)> = sets.values().collect();
+    order.sort_by_key(|(info_set, _)| std::cmp::Reverse(info_set.history.len()));
+    let mut choices: HashMap<String, usize> = HashMap::new();
+    for (info_set, nodes) in order {
+        let scores = (0..info_set.choices.len()).map(|a| score(info_set, nodes, a, &choices));
+        let best = scores.enumerate().fold((0, f64::NEG_INFINITY), |best, (a, s)| if s > best.1 + EPS { (a, s) } else { best }).0;
+        choices.insert(info_set.to_string(), best);
+    }
+    choices
+}
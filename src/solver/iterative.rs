@@ -0,0 +1,188 @@
+use crate::common::lp_solver::{DefaultSolver, Solver};
+
+use super::*;
+use super::infoset::{decide_deepest_first, InfoSetNodes};
+use std::collections::{BTreeMap, HashMap};
+
+type ResponseNodes<N> = InfoSetNodes<(N, Histories, f64)>;
+
+// Pure best response of the other player to opponent, and its value for the responding player
+pub fn best_response<G: Game, T: GameTree<G>>(tree: &T, opponent: &SequenceFormStrategy) -> (SequenceFormStrategy, f64) {
+    let player = 3 - opponent.player;
+    let sign = if player == 1 { 1. } else { -1. };
+    let mut sets: ResponseNodes<T::Node<'_>> = HashMap::new();
+    collect_rec(tree, tree.root(), Histories::default(), (1., 1.), opponent, &mut sets);
+    let choices = decide_deepest_first(&sets, |_, nodes, a, choices| {
+        nodes.iter().map(|(node, hist, reach)| match tree.child(*node, a) {
+            Some(child) if *reach > 0. => reach*response_value(tree, child, &hist.child(tree.node_type(*node), a), choices, opponent),
+            _ => 0.,
+        }).sum::<f64>()*sign
+    });
+    let mut ret = SequenceFormStrategy::new(player);
+    pure_rec(tree, tree.root(), &Histories::default(), 1., &choices, &mut ret);
+    let value = ret.expected_value(opponent, tree);
+    (ret, value)
+}
+
+// Sum of both players' gains from best responding, zero exactly at an equilibrium
+pub fn exploitability<G: Game, T: GameTree<G>>(tree: &T, s1: &SequenceFormStrategy, s2: &SequenceFormStrategy) -> f64 {
+    best_response(tree, s2).1 + best_response(tree, s1).1
+}
+
+// The responding player's nodes are collected with the chance and opponent reach of each, the player's own choices
+// left out
+fn collect_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, hist: Histories, reach: (f64, f64), opponent: &SequenceFormStrategy, sets: &mut ResponseNodes<T::Node<'a>>) {
+    let node_type = tree.node_type(node);
+    let info_set = hist.info_set(node_type);
+    for i in 0..tree.num_children(node) {
+        let Some(child) = tree.child(node, i) else { continue };
+        let next = match (node_type, &info_set) {
+            (NodeType::Random(_), _) => (reach.0*tree.prob(node).expect("random nodes should have prob")[i], reach.1),
+            (_, Some(info_set)) if info_set.player == opponent.player => (reach.0, opponent.weight(info_set, i)),
+            _ => reach,
+        };
+        collect_rec(tree, child, hist.child(node_type, i), next, opponent, sets);
+    }
+    if let Some(info_set) = info_set.filter(|i| i.player != opponent.player) {
+        sets.entry(info_set.to_string()).or_insert_with(|| (info_set, Vec::new())).1.push((node, hist, reach.0*reach.1));
+    }
+}
+
+// Value of node for P1 when the responding player follows choices and the opponent its behavioral strategy
+fn response_value<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, hist: &Histories, choices: &HashMap<String, usize>, opponent: &SequenceFormStrategy) -> f64 {
+    let node_type = tree.node_type(node);
+    let prob = match (node_type, hist.info_set(node_type)) {
+        (NodeType::End, _) => return tree.value(node).expect("end nodes should always have value"),
+        (NodeType::Random(_), _) => tree.prob(node).expect("random nodes should have prob").to_vec(),
+        (_, Some(info_set)) if info_set.player == opponent.player => opponent.behavioral(&info_set),
+        (_, Some(info_set)) => {
+            let mut prob = vec_of_repeat(info_set.choices.len(), 0.);
+            prob[choices[&info_set.to_string()]] = 1.;
+            prob
+        }
+        _ => vec![1.],
+    };
+    let mut value = 0.;
+    for (i, p) in prob.iter().enumerate() {
+        if let Some(child) = tree.child(node, i).filter(|_| *p > 0.) {
+            value += p*response_value(tree, child, &hist.child(node_type, i), choices, opponent);
+        }
+    }
+    value
+}
+
+fn pure_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, hist: &Histories, weight: f64, choices: &HashMap<String, usize>, ret: &mut SequenceFormStrategy) {
+    let node_type = tree.node_type(node);
+    let info_set = hist.info_set(node_type).filter(|i| i.player == ret.player);
+    for i in 0..tree.num_children(node) {
+        let mut child_weight = weight;
+        if let Some(info_set) = &info_set {
+            child_weight = if choices[&info_set.to_string()] == i { weight } else { 0. };
+            ret.weights.entry((info_set.to_string(), i)).or_insert(child_weight);
+        }
+        if let Some(child) = tree.child(node, i) {
+            pure_rec(tree, child, &hist.child(node_type, i), child_weight, choices, ret);
+        }
+    }
+}
+
+// Extensive-form fictitious play (Heinrich, Lanctot and Silver 2015): each player best responds to the other's
+// average, and averaging realization weights averages the behavioral strategies weighted by reach
+pub fn fictitious_play<G: Game, T: GameTree<G>>(tree: &T, iterations: usize) -> (SequenceFormStrategy, SequenceFormStrategy) {
+    let mut avg1 = best_response(tree, &SequenceFormStrategy::new(2)).0;
+    let mut avg2 = best_response(tree, &SequenceFormStrategy::new(1)).0;
+    for k in 1..=iterations {
+        let br1 = best_response(tree, &avg2).0;
+        let br2 = best_response(tree, &avg1).0;
+        let lambda = k as f64/(k+1) as f64;
+        avg1 = avg1.mix(&br1, lambda);
+        avg2 = avg2.mix(&br2, lambda);
+    }
+    (avg1, avg2)
+}
+
+// Grows sets of pure strategies with best responses to the restricted game's equilibrium. The best responses' values
+// bound P1's value in the full game, and it stops once they meet the restricted game's value, when its equilibrium is
+// one of the full game, or after max_iterations. Returns that equilibrium with P1's value in the restricted game and
+// the (lower, upper) bounds, whose difference is the equilibrium's exploitability
+pub fn double_oracle<G: Game, T: GameTree<G>>(tree: &T, max_iterations: usize) -> (SequenceFormStrategy, SequenceFormStrategy, f64, (f64, f64)) {
+    let mut pure1 = vec![best_response(tree, &SequenceFormStrategy::new(2)).0];
+    let mut pure2 = vec![best_response(tree, &SequenceFormStrategy::new(1)).0];
+    let mut payoffs = vec![vec![pure1[0].expected_value(&pure2[0], tree)]];
+    let mut iteration = 0;
+    loop {
+        let (x, value) = solve_matrix(&payoffs);
+        let transposed: Vec<Vec<f64>> = (0..pure2.len()).map(|j| payoffs.iter().map(|row| -row[j]).collect()).collect();
+        let (y, _) = solve_matrix(&transposed);
+        let mix1 = mixture(&pure1, &x);
+        let mix2 = mixture(&pure2, &y);
+        iteration += 1;
+        let (br1, upper) = best_response(tree, &mix2);
+        let (br2, p2_value) = best_response(tree, &mix1);
+        let bounds = (-p2_value, upper);
+        let converged = bounds.1 - value <= EPS && value - bounds.0 <= EPS;
+        // A best response already in its set can't close the gap, so with neither new the sets stop growing
+        let new1 = !pure1.contains(&br1);
+        let new2 = !pure2.contains(&br2);
+        if converged || iteration >= max_iterations || !(new1 || new2) {
+            return (mix1, mix2, value, bounds);
+        }
+        if new1 {
+            payoffs.push(pure2.iter().map(|s2| br1.expected_value(s2, tree)).collect());
+            pure1.push(br1);
+        }
+        if new2 {
+            for (row, s1) in payoffs.iter_mut().zip(&pure1) {
+                row.push(s1.expected_value(&br2, tree));
+            }
+            pure2.push(br2);
+        }
+    }
+}
+
+fn mixture(pure: &[SequenceFormStrategy], prob: &[f64]) -> SequenceFormStrategy {
+    let mut weights = BTreeMap::new();
+    for (s, p) in pure.iter().zip(prob) {
+        for (k, w) in &s.weights {
+            *weights.entry(k.clone()).or_insert(0.) += p*w;
+        }
+    }
+    SequenceFormStrategy { player: pure[0].player, weights }
+}
+
+// Maximin mixture over the rows of a matrix game, and its value
+fn solve_matrix(payoffs: &[Vec<f64>]) -> (Vec<f64>, f64) {
+    let mut s = DefaultSolver::new();
+    let v = s.new_var();
+    let x: Vec<usize> = payoffs.iter().map(|_| s.new_var()).collect();
+    for &xi in &x {
+        s.add_constraint(&vec![(1., xi)], Ordering::Greater, 0.);
+    }
+    s.add_constraint(&x.iter().map(|&xi| (1., xi)).collect(), Ordering::Equal, 1.);
+    for j in 0..payoffs[0].len() {
+        let mut row = vec![(-1., v)];
+        row.extend(x.iter().zip(payoffs).map(|(&xi, r)| (r[j], xi)));
+        s.add_constraint(&row, Ordering::Greater, 0.);
+    }
+    let sol = s.solve(vec![(1., v)]);
+    (x.iter().map(|&xi| sol(xi).max(0.)).collect(), sol(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn double_oracle_bounds_close_on_the_value() {
+        let tree = Tree::new(RPS::new());
+        let (s1, s2, value, (lower, upper)) = double_oracle(&tree, 1);
+        assert!(lower <= value + EPS && value <= upper + EPS);
+        assert!(upper - lower > 0.5);
+        assert!((upper - lower - exploitability(&tree, &s1, &s2)).abs() < 1e-9);
+
+        let (_, _, value, (lower, upper)) = double_oracle(&tree, 100);
+        assert!(value.abs() < 1e-9);
+        assert!(upper - lower < 1e-9);
+    }
+}
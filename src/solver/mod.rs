@@ -1,11 +1,75 @@
 use crate::common::*;
+use std::sync::Arc;
 
 mod explorer;
-pub use explorer::{expand, expand_full};
+pub use explorer::{evaluate_frontier, expand, expand_full, expand_full_observed, expand_observed, rollout};
+#[cfg(feature = "parallel")]
+pub use explorer::{evaluate_frontier_par, expand_full_par};
 
 mod exact;
+pub use exact::{solve, solve_abstracted, solve_observed, solve_refined, solve_single, solve_single_observed, solve_with_leaves, LeafEvaluator, NoLeaves, Refinement, Session};
+
+mod abstraction;
+pub use abstraction::{kmeans, Abstraction, KMeansAbstraction, NoAbstraction};
+
+mod action_abstraction;
+pub use action_abstraction::{ActionAbstraction, KeepChoices, NoActionAbstraction, Restricted, TranslateNearest, TranslatingPlayer};
+
+mod resolve;
+pub use resolve::{resolve, resolve_with_leaves};
+
+mod recall;
+pub use recall::{assert_perfect_recall, check_recall, RecallViolation};
+
+mod stats;
+pub use stats::{estimate_size, tree_stats, NodeCounts, SizeEstimate, TreeStats};
+
+mod observer;
+pub use observer::{LpFileObserver, NoObserver, Observer, PrintObserver};
+
+mod arena;
+pub use arena::{history_key, state_key, ArenaTree, NodeId};
+
+mod infoset;
+pub use infoset::{Histories, InfoSet, Observation};
+
+mod policy;
+pub use policy::{policy_tables, PolicyPlayer, PolicyTable};
+
+mod sequence;
+pub use sequence::SequenceFormStrategy;
+
+mod iterative;
+pub use iterative::{best_response, double_oracle, exploitability, fictitious_play};
+
+mod value_net;
+pub use value_net::{feature_width, path_features, Mlp, ValueNet};
+
+mod dataset;
+pub use dataset::{datasets, write_npy, Dataset};
+
+mod multi;
+pub use multi::{cfr, cfr_abstracted, nash_conv, MultiNodeType, MultiTree};
+
+mod commitment;
+pub use commitment::{correlated, stackelberg, CorrelatedPlan};
+
+mod dot;
+pub use dot::{to_dot, write_dot, DotOptions};
+
+#[cfg(feature = "serde")]
+mod persist;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "<G::P1 as PlayerTraits>::Message: serde::Serialize, <G::P2 as PlayerTraits>::Message: serde::Serialize, \
+        <G::P1 as PlayerTraits>::Choice: serde::Serialize, <G::P2 as PlayerTraits>::Choice: serde::Serialize, \
+        G::RandomChoice: serde::Serialize",
+    deserialize = "<G::P1 as PlayerTraits>::Message: serde::Deserialize<'de>, <G::P2 as PlayerTraits>::Message: serde::Deserialize<'de>, \
+        <G::P1 as PlayerTraits>::Choice: serde::Deserialize<'de>, <G::P2 as PlayerTraits>::Choice: serde::Deserialize<'de>, \
+        G::RandomChoice: serde::Deserialize<'de>",
+)))]
 pub enum NodeType<G: Game> {
     Message1(<G::P1 as PlayerTraits>::Message),
     Message2(<G::P2 as PlayerTraits>::Message),
@@ -15,74 +79,197 @@ pub enum NodeType<G: Game> {
     End,
 }
 
+impl<G: Game> NodeType<G> {
+    pub fn child_label(&self, i: usize) -> String {
+        match self {
+            NodeType::Player1(c) => c[i].to_string(),
+            NodeType::Player2(c) => c[i].to_string(),
+            NodeType::Random(r) => r[i].to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+// A saved game state at the start of a step and the path from the root that reaches it. Nodes below it share it
+// until a later one is saved
+#[derive(Debug)]
+struct Snapshot<G> {
+    game: G,
+    path: Vec<usize>,
+}
+
+// Where a node is: the snapshot and the choices made since it
+type NodePath<G> = (Arc<Snapshot<G>>, Vec<usize>);
+
+// Serialized through persist, which stores the shared snapshots once
 #[derive(Debug, Clone)]
 pub struct Tree<G: Game> {
     node_type: NodeType<G>,
     children: Vec<Option<Tree<G>>>,
-    path: (G, Vec<usize>),
+    path: NodePath<G>,
     value: Option<f64>,
     prob: Option<Vec<f64>>
 }
 
+impl<G: Game> Tree<G> {
+    pub fn node_type(&self) -> &NodeType<G> {
+        &self.node_type
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn child(&self, i: usize) -> Option<&Tree<G>> {
+        self.children[i].as_ref()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+
+    pub fn prob(&self) -> Option<&Vec<f64>> {
+        self.prob.as_ref()
+    }
+
+    // Child indices from the root to this node
+    pub fn path(&self) -> Vec<usize> {
+        [&self.path.0.path[..], &self.path.1[..]].concat()
+    }
+}
+
 impl<G: Game + Clone + Debug> Tree<G> {
-    fn new_node(node_type: NodeType<G>, root: G, path: Vec<usize>, child_amt: usize) -> Tree<G> {
+    fn new_node(node_type: NodeType<G>, snapshot: Arc<Snapshot<G>>, path: Vec<usize>, child_amt: usize) -> Tree<G> {
         Tree {
             node_type: node_type,
             children: vec_of_repeat(child_amt, None),
-            path: (root, path),
+            path: (snapshot, path),
             value: None,
             prob: None
         }
     }
 
     pub fn new(g: G) -> Tree<G> {
-        let mut tree = explorer::make_node(g, vec![]);
-        explorer::expand_full(&mut tree);
-        exact::solve(&mut tree);
+        Tree::new_observed(g, &mut NoObserver)
+    }
+
+    // Just the root node, to be grown with expand() and expand_full()
+    pub fn new_root(g: G) -> Tree<G> {
+        explorer::make_node(Arc::new(Snapshot { game: g, path: vec![] }), vec![])
+    }
+
+    pub fn new_observed(g: G, obs: &mut dyn Observer<G>) -> Tree<G> {
+        let mut tree = Tree::new_root(g);
+        explorer::expand_full_observed(&mut tree, obs);
+        exact::solve_observed(&mut tree, obs);
         tree
     }
 }
 
-pub struct TreeGame<G: Game> {
-    state: Tree<G>
+// Common access to the recursive Tree and the ArenaTree, so solving and playing work on either
+pub trait GameTree<G: Game> {
+    type Node<'a>: Copy where Self: 'a;
+    fn root(&self) -> Self::Node<'_>;
+    fn node_type<'a>(&'a self, n: Self::Node<'a>) -> &'a NodeType<G>;
+    fn num_children<'a>(&'a self, n: Self::Node<'a>) -> usize;
+    fn child<'a>(&'a self, n: Self::Node<'a>, i: usize) -> Option<Self::Node<'a>>;
+    fn value<'a>(&'a self, n: Self::Node<'a>) -> Option<f64>;
+    fn prob<'a>(&'a self, n: Self::Node<'a>) -> Option<&'a [f64]>;
+    // Makes child i the root, so walking down the tree never looks a node up from the old root again
+    fn descend(&mut self, i: usize);
+    // path is the list of child indices from the root
+    fn set_solution(&mut self, path: &[usize], prob: Vec<f64>, value: f64);
+
+    fn find(&self, path: &[usize]) -> Option<Self::Node<'_>> {
+        let mut node = self.root();
+        for &i in path {
+            node = self.child(node, i)?;
+        }
+        Some(node)
+    }
+}
+
+impl<G: Game> GameTree<G> for Tree<G> {
+    type Node<'a> = &'a Tree<G> where G: 'a;
+
+    fn root(&self) -> &Tree<G> {
+        self
+    }
+    fn node_type<'a>(&'a self, n: &'a Tree<G>) -> &'a NodeType<G> {
+        &n.node_type
+    }
+    fn num_children<'a>(&'a self, n: &'a Tree<G>) -> usize {
+        n.children.len()
+    }
+    fn child<'a>(&'a self, n: &'a Tree<G>, i: usize) -> Option<&'a Tree<G>> {
+        n.children[i].as_ref()
+    }
+    fn value<'a>(&'a self, n: &'a Tree<G>) -> Option<f64> {
+        n.value
+    }
+    fn prob<'a>(&'a self, n: &'a Tree<G>) -> Option<&'a [f64]> {
+        n.prob.as_deref()
+    }
+    // The rest of the tree is dropped
+    fn descend(&mut self, i: usize) {
+        let child = self.children[i].take().expect("child should be expanded");
+        *self = child;
+    }
+    fn set_solution(&mut self, path: &[usize], prob: Vec<f64>, value: f64) {
+        let mut node = self;
+        for &i in path {
+            node = node.children[i].as_mut().expect("solution path should be expanded");
+        }
+        node.prob = Some(prob);
+        node.value = Some(value);
+    }
+}
+
+// Plays out a solved tree, descending into it as it goes
+pub struct TreeGame<G: Game, T: GameTree<G> = Tree<G>, O: Observer<G> = NoObserver> {
+    tree: T,
+    observer: O,
+    game_type: PhantomData<G>,
+}
+
+impl<G: Game, T: GameTree<G>> TreeGame<G, T> {
+    pub fn new(tree: T) -> TreeGame<G, T> {
+        TreeGame::with_observer(tree, NoObserver)
+    }
 }
 
-impl<G: Game> TreeGame<G> {
-    pub fn new(tree: Tree<G>) -> TreeGame<G> {
-        TreeGame { state: tree }
+impl<G: Game, T: GameTree<G>, O: Observer<G>> TreeGame<G, T, O> {
+    pub fn with_observer(tree: T, observer: O) -> TreeGame<G, T, O> {
+        TreeGame { tree, observer, game_type: PhantomData }
     }
 }
 
-impl<G: Game + Clone> Game for TreeGame<G> {
+impl<G: Game + Clone, T: GameTree<G>, O: Observer<G>> Game for TreeGame<G, T, O> {
     type P1 = G::P1;
     type P2 = G::P2;
     type RandomChoice = G::RandomChoice;
     fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
-        println!("Value: {:?}, Prob: {:?}", self.state.value, self.state.prob);
-        let child = match self.state.node_type.clone() {
+        let node = self.tree.root();
+        let prob = self.tree.prob(node);
+        self.observer.visit(self.tree.node_type(node), self.tree.value(node), prob);
+        let child = match self.tree.node_type(node) {
             NodeType::Message1(m) => {
-                g.p1_message(&m)?;
-                self.state.children[0].clone()
+                g.p1_message(m)?;
+                0
             }
             NodeType::Message2(m) => {
-                g.p2_message(&m)?;
-                self.state.children[0].clone()
-            }
-            NodeType::Player1(c) => {
-                self.state.children[g.p1_choice(&c)?].clone()
-            }
-            NodeType::Player2(c) => {
-                self.state.children[g.p2_choice(&c)?].clone()
-            }
-            NodeType::Random(v) => {
-                self.state.children[g.random(&self.state.prob.clone()?, &v)?].clone()
+                g.p2_message(m)?;
+                0
             }
+            NodeType::Player1(c) => g.p1_choice(c)?,
+            NodeType::Player2(c) => g.p2_choice(c)?,
+            NodeType::Random(v) => g.random(&prob?.to_vec(), v)?,
             NodeType::End => {
-                g.end(self.state.value.expect("end node should have value"));
+                g.end(self.tree.value(node).expect("end node should have value"));
                 return None;
             }
         };
-        self.state = child.expect("tree should be fully expanded");
+        self.tree.descend(child);
         Some(())
     }
-}
\ No newline at end of file
+}
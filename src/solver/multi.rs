@@ -0,0 +1,506 @@
+use super::*;
+use super::explorer::{replay_steps, Replay};
+use super::infoset::{decide_deepest_first, InfoSetNodes};
+use super::abstraction::Abstraction;
+use crate::common::multi::{MultiGame, MultiGameInterface, Side, ZeroSum};
+use std::collections::HashMap;
+
+type Message<G> = <<G as MultiGame>::Traits as PlayerTraits>::Message;
+type Choice<G> = <<G as MultiGame>::Traits as PlayerTraits>::Choice;
+
+#[derive(Debug, Clone)]
+pub enum MultiNodeType<G: MultiGame> {
+    Message(usize, Message<G>),
+    Choice(usize, Vec<Choice<G>>),
+    Random(Vec<G::RandomChoice>),
+    End,
+}
+
+// A fully expanded MultiGame. payoffs holds every player's payoff at end nodes, and their expected payoffs once
+// solved; prob holds the random distribution, or the solved strategy at choice nodes
+#[derive(Debug, Clone)]
+pub struct MultiTree<G: MultiGame> {
+    node_type: MultiNodeType<G>,
+    children: Vec<MultiTree<G>>,
+    prob: Option<Vec<f64>>,
+    payoffs: Option<Vec<f64>>,
+    num_players: usize,
+}
+
+enum Recorded<G: MultiGame> {
+    Node(MultiNodeType<G>, usize, Option<Vec<f64>>),
+    End(Vec<f64>),
+}
+
+// Records the first node past the replayed path, like explorer's MockInterface
+struct Recorder<'a, G: MultiGame> {
+    replay: Replay<'a>,
+    recorded: Option<Recorded<G>>,
+}
+
+impl<G: MultiGame> Recorder<'_, G> {
+    fn record(&mut self, recorded: Recorded<G>) {
+        assert!(self.recorded.is_none(), "recording should happen exactly once");
+        self.recorded = Some(recorded);
+    }
+}
+
+impl<G: MultiGame> MultiGameInterface<G> for Recorder<'_, G> {
+    fn random(&mut self, p: &Vec<f64>, v: &Vec<G::RandomChoice>) -> Option<usize> {
+        self.replay.choice().or_else(|| {
+            self.record(Recorded::Node(MultiNodeType::Random(v.clone()), v.len(), Some(p.clone())));
+            None
+        })
+    }
+    fn choice(&mut self, player: usize, v: &Vec<Choice<G>>) -> Option<usize> {
+        self.replay.choice().or_else(|| {
+            self.record(Recorded::Node(MultiNodeType::Choice(player, v.clone()), v.len(), None));
+            None
+        })
+    }
+    fn message(&mut self, player: usize, msg: &Message<G>) -> Option<()> {
+        self.replay.message().or_else(|| {
+            self.record(Recorded::Node(MultiNodeType::Message(player, msg.clone()), 1, None));
+            None
+        })
+    }
+    fn end(&mut self, payoffs: &[f64]) {
+        self.replay.end();
+        self.record(Recorded::End(payoffs.to_vec()));
+    }
+}
+
+impl<G: MultiGame + Clone> MultiTree<G> {
+    pub fn new(g: G) -> MultiTree<G> {
+        let num_players = g.num_players();
+        build_rec(Arc::new(g), Vec::new(), num_players)
+    }
+}
+
+// Replays path from the state saved at the start of its step, as explorer::make_node does
+fn build_rec<G: MultiGame + Clone>(root: Arc<G>, path: Vec<usize>, num_players: usize) -> MultiTree<G> {
+    let mut recorder = Recorder { replay: Replay::new(&path), recorded: None };
+    let (root, start) = replay_steps(root, &mut recorder, |game, recorder| game.step(recorder), |recorder| recorder.replay.position());
+    let step_path = &path[start..];
+    match recorder.recorded.expect("recording should happen exactly once") {
+        Recorded::End(payoffs) => MultiTree { node_type: MultiNodeType::End, children: Vec::new(), prob: None, payoffs: Some(payoffs), num_players },
+        Recorded::Node(node_type, n, prob) => {
+            let children = (0..n).map(|i| {
+                let mut child_path = step_path.to_vec();
+                child_path.push(i);
+                build_rec(root.clone(), child_path, num_players)
+            }).collect();
+            MultiTree { node_type, children, prob, payoffs: None, num_players }
+        }
+    }
+}
+
+impl<G: MultiGame> MultiTree<G> {
+    pub fn node_type(&self) -> &MultiNodeType<G> {
+        &self.node_type
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn child(&self, i: usize) -> &MultiTree<G> {
+        &self.children[i]
+    }
+
+    pub fn prob(&self) -> Option<&Vec<f64>> {
+        self.prob.as_ref()
+    }
+
+    pub fn payoffs(&self) -> Option<&Vec<f64>> {
+        self.payoffs.as_ref()
+    }
+
+    fn observe(&self, histories: &mut [Vec<Observation>], i: usize) {
+        match &self.node_type {
+            MultiNodeType::Message(p, m) => histories[p - 1].push(Observation::Message(m.to_string())),
+            MultiNodeType::Choice(p, c) => histories[p - 1].push(Observation::Choice(c[i].to_string())),
+            MultiNodeType::Random(_) | MultiNodeType::End => {}
+        }
+    }
+
+    fn info_set(&self, histories: &[Vec<Observation>]) -> Option<InfoSet> {
+        match &self.node_type {
+            MultiNodeType::Choice(p, c) => Some(InfoSet::new(*p, &histories[p - 1], c)),
+            _ => None,
+        }
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.num_players
+    }
+}
+
+// The tree in preorder, with information sets as indices. Grouped flattening can merge several real information
+// sets into one
+pub(super) struct Flat {
+    pub(super) nodes: Vec<FlatNode>,
+    pub(super) info_sets: Vec<InfoSet>,
+}
+
+pub(super) struct FlatNode {
+    // 0 for nodes where no player chooses
+    pub(super) player: usize,
+    pub(super) info_set: usize,
+    pub(super) children: Vec<usize>,
+    pub(super) prob: Vec<f64>,
+    pub(super) payoffs: Vec<f64>,
+}
+
+// Turns every message a player got so far into the observations their information set is keyed by, after their choices
+type Bucketing<'a, G> = dyn FnMut(usize, &[Message<G>]) -> Vec<Observation> + 'a;
+
+impl Flat {
+    pub(super) fn new<G: MultiGame>(tree: &MultiTree<G>) -> Flat {
+        Flat::grouped(tree, &mut |_, msgs| msgs.iter().map(|m| Observation::Message(m.to_string())).collect())
+    }
+
+    // Information sets are keyed by each player's choices and bucketed messages instead of everything they observed
+    pub(super) fn grouped<G: MultiGame>(tree: &MultiTree<G>, bucket: &mut Bucketing<'_, G>) -> Flat {
+        let mut flat = Flat { nodes: Vec::new(), info_sets: Vec::new() };
+        let players = tree.num_players();
+        let keys = Keys { keyed: vec_of_repeat(players, Vec::new()), msgs: vec_of_repeat(players, Vec::new()) };
+        flatten_rec(tree, &keys, bucket, &mut HashMap::new(), &mut flat);
+        flat
+    }
+
+    // Writes strategies, by information set, into the tree along with every node's expected payoffs, and returns
+    // them as one PolicyTable per player, keyed by the real information sets
+    pub(super) fn apply<G: MultiGame>(&self, tree: &mut MultiTree<G>, strategies: &[Vec<f64>]) -> Vec<PolicyTable> {
+        let players = tree.num_players();
+        let mut tables = vec_of_repeat(players, PolicyTable::default());
+        apply_rec(tree, &vec_of_repeat(players, Vec::new()), self, &mut 0, strategies, &mut tables);
+        tables
+    }
+}
+
+// Each player's key up to their last choice, and every message they were sent
+struct Keys<G: MultiGame> {
+    keyed: Vec<Vec<Observation>>,
+    msgs: Vec<Vec<Message<G>>>,
+}
+
+impl<G: MultiGame> Clone for Keys<G> {
+    fn clone(&self) -> Self {
+        Keys { keyed: self.keyed.clone(), msgs: self.msgs.clone() }
+    }
+}
+
+fn flatten_rec<G: MultiGame>(tree: &MultiTree<G>, keys: &Keys<G>, bucket: &mut Bucketing<'_, G>, ids: &mut HashMap<InfoSet, usize>, flat: &mut Flat) -> usize {
+    let id = flat.nodes.len();
+    let (player, info_set, key) = match &tree.node_type {
+        MultiNodeType::Choice(p, c) => {
+            let mut key = keys.keyed[p - 1].clone();
+            key.extend(bucket(*p, &keys.msgs[p - 1]));
+            let info_set = InfoSet::new(*p, &key, c);
+            let next = ids.len();
+            let i = *ids.entry(info_set.clone()).or_insert(next);
+            if i == flat.info_sets.len() {
+                flat.info_sets.push(info_set);
+            }
+            (*p, i, key)
+        }
+        _ => (0, 0, Vec::new()),
+    };
+    flat.nodes.push(FlatNode {
+        player,
+        info_set,
+        children: Vec::new(),
+        prob: if let MultiNodeType::Random(_) = tree.node_type { tree.prob.clone().expect("random nodes should have prob") } else { Vec::new() },
+        payoffs: if let MultiNodeType::End = tree.node_type { tree.payoffs.clone().expect("end nodes should have payoffs") } else { Vec::new() },
+    });
+    for (i, child) in tree.children.iter().enumerate() {
+        let mut next = keys.clone();
+        match &tree.node_type {
+            MultiNodeType::Message(p, m) => next.msgs[p - 1].push(m.clone()),
+            MultiNodeType::Choice(p, c) => {
+                next.keyed[p - 1] = key.clone();
+                next.keyed[p - 1].push(Observation::Choice(c[i].to_string()));
+            }
+            MultiNodeType::Random(_) | MultiNodeType::End => {}
+        }
+        let c = flatten_rec(child, &next, bucket, ids, flat);
+        flat.nodes[id].children.push(c);
+    }
+    id
+}
+
+fn regret_matching(regrets: &[f64]) -> Vec<f64> {
+    let total: f64 = regrets.iter().map(|r| r.max(0.)).sum();
+    if total > 0. {
+        regrets.iter().map(|r| r.max(0.) / total).collect()
+    } else {
+        vec_of_repeat(regrets.len(), 1. / regrets.len() as f64)
+    }
+}
+
+fn normalized(sums: &[f64]) -> Vec<f64> {
+    let total: f64 = sums.iter().sum();
+    if total > 0. {
+        sums.iter().map(|s| s / total).collect()
+    } else {
+        vec_of_repeat(sums.len(), 1. / sums.len() as f64)
+    }
+}
+
+// reach[0] is chance's contribution and reach[p] player p's. Returns every player's payoff under the current strategies
+fn cfr_rec(flat: &Flat, node: usize, reach: &mut Vec<f64>, current: &[Vec<f64>], regrets: &mut [Vec<f64>], sums: &mut [Vec<f64>]) -> Vec<f64> {
+    let n = &flat.nodes[node];
+    if n.children.is_empty() {
+        return n.payoffs.clone();
+    }
+    let (who, prob) = match n.player {
+        0 if n.prob.is_empty() => (0, vec![1.]),
+        0 => (0, n.prob.clone()),
+        p => (p, current[n.info_set].clone()),
+    };
+    let before = reach[who];
+    let mut value = vec_of_repeat(reach.len() - 1, 0.);
+    let mut child_values = Vec::with_capacity(prob.len());
+    for (&c, &p) in n.children.iter().zip(&prob) {
+        reach[who] = before * p;
+        let v = cfr_rec(flat, c, reach, current, regrets, sums);
+        for (total, x) in value.iter_mut().zip(&v) {
+            *total += p * x;
+        }
+        child_values.push(v);
+    }
+    reach[who] = before;
+    if who > 0 {
+        let others: f64 = reach.iter().enumerate().filter(|(j, _)| *j != who).map(|(_, r)| r).product();
+        for (a, v) in child_values.iter().enumerate() {
+            regrets[n.info_set][a] += others * (v[who - 1] - value[who - 1]);
+            sums[n.info_set][a] += before * prob[a];
+        }
+    }
+    value
+}
+
+// Vanilla counterfactual regret minimization, with every player updated each iteration. The average strategies are
+// written into the tree, and returned as one PolicyTable per player. They converge to a Nash equilibrium for two
+// players and zero sum; otherwise only the players' regrets are guaranteed to vanish, see nash_conv
+pub fn cfr<G: MultiGame>(tree: &mut MultiTree<G>, iterations: usize) -> Vec<PolicyTable> {
+    let flat = Flat::new(tree);
+    cfr_flat(tree, &flat, iterations)
+}
+
+// CFR on a two-player game whose information sets are collapsed by abstraction, as in exact's solve_abstracted: a
+// player's messages so far are replaced by their bucket. Every real information set plays its bucket's strategy
+pub fn cfr_abstracted<G: Game, A: Abstraction<G>>(tree: &mut MultiTree<ZeroSum<G>>, abstraction: &A, iterations: usize) -> Vec<PolicyTable> {
+    let (mut ids1, mut ids2) = (HashMap::new(), HashMap::new());
+    let flat = Flat::grouped(tree, &mut |p, msgs| {
+        let bucket = if p == 1 {
+            let msgs: Vec<_> = msgs.iter().map(|m| match m {
+                Side::P1(m) => m.clone(),
+                Side::P2(_) => panic!("player 1 should only be sent P1 messages"),
+            }).collect();
+            index(&mut ids1, abstraction.bucket1(&msgs))
+        } else {
+            let msgs: Vec<_> = msgs.iter().map(|m| match m {
+                Side::P2(m) => m.clone(),
+                Side::P1(_) => panic!("player 2 should only be sent P2 messages"),
+            }).collect();
+            index(&mut ids2, abstraction.bucket2(&msgs))
+        };
+        vec![Observation::Message(format!("bucket {bucket}"))]
+    });
+    cfr_flat(tree, &flat, iterations)
+}
+
+fn index<K: Eq + Hash>(ids: &mut HashMap<K, usize>, key: K) -> usize {
+    let next = ids.len();
+    *ids.entry(key).or_insert(next)
+}
+
+fn cfr_flat<G: MultiGame>(tree: &mut MultiTree<G>, flat: &Flat, iterations: usize) -> Vec<PolicyTable> {
+    let players = tree.num_players();
+    let mut regrets: Vec<Vec<f64>> = flat.info_sets.iter().map(|i| vec_of_repeat(i.choices.len(), 0.)).collect();
+    let mut sums = regrets.clone();
+    for _ in 0..iterations {
+        let current: Vec<Vec<f64>> = regrets.iter().map(|r| regret_matching(r)).collect();
+        cfr_rec(flat, 0, &mut vec_of_repeat(players + 1, 1.), &current, &mut regrets, &mut sums);
+    }
+    let average: Vec<Vec<f64>> = sums.iter().map(|s| normalized(s)).collect();
+    flat.apply(tree, &average)
+}
+
+// Sets choice probabilities from strategies by information set, then every node's expected payoffs
+fn apply_rec<G: MultiGame>(tree: &mut MultiTree<G>, histories: &[Vec<Observation>], flat: &Flat, next: &mut usize, strategies: &[Vec<f64>], tables: &mut [PolicyTable]) -> Vec<f64> {
+    let node = &flat.nodes[*next];
+    *next += 1;
+    if node.player > 0 {
+        let prob = strategies[node.info_set].clone();
+        let info_set = tree.info_set(histories).expect("choice nodes should have an information set");
+        tables[node.player - 1].entries.insert(info_set.to_string(), info_set.choices.iter().cloned().zip(prob.iter().cloned()).collect());
+        tree.prob = Some(prob);
+    }
+    if tree.children.is_empty() {
+        return tree.payoffs.clone().expect("end nodes should have payoffs");
+    }
+    let prob = tree.prob.clone().unwrap_or_else(|| vec![1.]);
+    let mut value = vec_of_repeat(histories.len(), 0.);
+    for (i, p) in prob.iter().enumerate() {
+        let mut child_histories = histories.to_vec();
+        tree.observe(&mut child_histories, i);
+        let v = apply_rec(&mut tree.children[i], &child_histories, flat, next, strategies, tables);
+        for (total, x) in value.iter_mut().zip(v) {
+            *total += p * x;
+        }
+    }
+    tree.payoffs = Some(value.clone());
+    value
+}
+
+// How much each player gains by best responding while everyone else keeps the tree's strategies; the sum is zero
+// exactly at a Nash equilibrium. The tree should be solved
+pub fn nash_conv<G: MultiGame>(tree: &MultiTree<G>) -> Vec<f64> {
+    let players = tree.num_players();
+    let root = tree.payoffs.as_ref().expect("tree should be solved");
+    (1..=players).map(|p| best_response_value(tree, p, players) - root[p - 1]).collect()
+}
+
+type ResponseNodes<'a, G> = InfoSetNodes<(&'a MultiTree<G>, Vec<Observation>, f64)>;
+
+fn best_response_value<G: MultiGame>(tree: &MultiTree<G>, player: usize, players: usize) -> f64 {
+    let mut sets: ResponseNodes<G> = HashMap::new();
+    collect_rec(tree, &vec_of_repeat(players, Vec::new()), player, 1., &mut sets);
+    let choices = decide_deepest_first(&sets, |info_set, nodes, a, choices| nodes.iter().map(|(node, hist, reach)| {
+        let mut next = hist.clone();
+        next.push(Observation::Choice(info_set.choices[a].clone()));
+        reach * response_value(&node.children[a], &next, player, choices)
+    }).sum());
+    response_value(tree, &[], player, &choices)
+}
+
+fn collect_rec<'a, G: MultiGame>(tree: &'a MultiTree<G>, histories: &[Vec<Observation>], player: usize, reach: f64, sets: &mut ResponseNodes<'a, G>) {
+    if let Some(info_set) = tree.info_set(histories).filter(|i| i.player == player) {
+        let hist = histories[player - 1].clone();
+        sets.entry(info_set.to_string()).or_insert_with(|| (info_set, Vec::new())).1.push((tree, hist, reach));
+    }
+    for (i, child) in tree.children.iter().enumerate() {
+        let p = match &tree.node_type {
+            MultiNodeType::Choice(q, _) if *q == player => 1.,
+            MultiNodeType::Choice(..) | MultiNodeType::Random(_) => tree.prob.as_ref().expect("tree should be solved")[i],
+            _ => 1.,
+        };
+        let mut next = histories.to_vec();
+        tree.observe(&mut next, i);
+        collect_rec(child, &next, player, reach * p, sets);
+    }
+}
+
+// Payoff of player when they follow choices and everyone else the tree's strategies. hist is the player's own history
+fn response_value<G: MultiGame>(tree: &MultiTree<G>, hist: &[Observation], player: usize, choices: &HashMap<String, usize>) -> f64 {
+    let observe = |i: usize| {
+        let mut next = hist.to_vec();
+        match &tree.node_type {
+            MultiNodeType::Message(p, m) if *p == player => next.push(Observation::Message(m.to_string())),
+            MultiNodeType::Choice(p, c) if *p == player => next.push(Observation::Choice(c[i].to_string())),
+            _ => {}
+        }
+        next
+    };
+    match &tree.node_type {
+        MultiNodeType::End => tree.payoffs.as_ref().expect("end nodes should have payoffs")[player - 1],
+        MultiNodeType::Choice(p, c) if *p == player => {
+            let a = choices[&InfoSet::new(player, hist, c).to_string()];
+            response_value(&tree.children[a], &observe(a), player, choices)
+        }
+        _ => {
+            let prob = tree.prob.clone().unwrap_or_else(|| vec![1.]);
+            tree.children.iter().enumerate().filter(|(i, _)| prob[*i] > 0.)
+                .map(|(i, child)| prob[i] * response_value(child, &observe(i), player, choices)).sum()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct N(i32);
+
+    impl Display for N {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Serializable for N {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = N;
+        type Choice = N;
+    }
+
+    // P1 is told a card from 0 to 3 and wins 1 for guessing its parity, or loses 1
+    #[derive(Clone, Debug, Default)]
+    struct Parity {
+        card: Option<i32>,
+    }
+
+    impl Game for Parity {
+        type P1 = Traits;
+        type P2 = Traits;
+        type RandomChoice = N;
+        fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+            let card = match self.card {
+                Some(card) => card,
+                None => {
+                    let card = g.random(&vec![0.25; 4], &(0..4).map(N).collect())? as i32;
+                    g.p1_message(&N(card))?;
+                    self.card = Some(card);
+                    return Some(());
+                }
+            };
+            let guess = g.p1_choice(&vec![N(0), N(1)])? as i32;
+            g.end(if guess == card % 2 { 1. } else { -1. });
+            None
+        }
+    }
+
+    // Buckets P1's cards by parity, or all into one
+    struct Cards {
+        parity: bool,
+    }
+
+    impl Abstraction<Parity> for Cards {
+        type Bucket1 = i32;
+        type Bucket2 = ();
+        fn bucket1(&self, msgs: &[N]) -> i32 {
+            msgs.iter().map(|m| if self.parity { m.0 % 2 } else { 0 }).sum()
+        }
+        fn bucket2(&self, _: &[N]) {}
+    }
+
+    #[test]
+    fn abstracted_cfr_shares_strategies_within_buckets() {
+        let mut tree = MultiTree::new(ZeroSum(Parity::default()));
+        let tables = cfr_abstracted(&mut tree, &Cards { parity: true }, 100);
+        assert!((tree.payoffs().expect("tree should be solved")[0] - 1.).abs() < 0.05);
+        assert_eq!(4, tables[0].entries.len());
+
+        let mut tree = MultiTree::new(ZeroSum(Parity::default()));
+        let tables = cfr_abstracted(&mut tree, &Cards { parity: false }, 100);
+        assert!(tree.payoffs().expect("tree should be solved")[0].abs() < 1e-9);
+        let mut strategies = tables[0].entries.values();
+        let first = strategies.next().expect("P1 should have information sets");
+        assert!(strategies.all(|s| s == first));
+    }
+}
@@ -0,0 +1,89 @@
+use super::*;
+use crate::common::lp_solver::SparseModel;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+// Hooks into tree exploration, solving and playing; every method defaults to doing nothing
+pub trait Observer<G: Game> {
+    fn visit(&mut self, _node_type: &NodeType<G>, _value: Option<f64>, _prob: Option<&[f64]>) {}
+    fn expand(&mut self, _node: &Tree<G>) {}
+    fn lp_built(&mut self, _player: usize, _lp: &SparseModel, _objective: &[(f64, usize)]) {}
+    fn lp_solved(&mut self, _player: usize, _objective: f64) {}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl<G: Game> Observer<G> for NoObserver {}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrintObserver;
+
+impl<G: Game> Observer<G> for PrintObserver {
+    fn visit(&mut self, _node_type: &NodeType<G>, value: Option<f64>, prob: Option<&[f64]>) {
+        println!("Value: {value:?}, Prob: {prob:?}");
+    }
+    fn expand(&mut self, node: &Tree<G>) {
+        println!("Expanded {:?}", node.path());
+    }
+    fn lp_built(&mut self, player: usize, lp: &SparseModel, _objective: &[(f64, usize)]) {
+        println!("LP for P{player}: {}", lp.stats());
+    }
+    fn lp_solved(&mut self, player: usize, objective: f64) {
+        println!("LP for P{player} solved: {objective}");
+    }
+}
+
+// Writes each LP to {prefix}_p{player}.lp and .mps before it is solved. A file that can't be written doesn't stop the
+// solve; its error is kept in errors
+#[derive(Debug)]
+pub struct LpFileObserver {
+    pub prefix: PathBuf,
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+impl LpFileObserver {
+    pub fn new(prefix: impl Into<PathBuf>) -> LpFileObserver {
+        LpFileObserver { prefix: prefix.into(), errors: Vec::new() }
+    }
+
+    fn write(&mut self, path: PathBuf, contents: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+        let result = File::create(&path).and_then(|file| {
+            let mut w = BufWriter::new(file);
+            contents(&mut w)?;
+            w.flush()
+        });
+        if let Err(e) = result {
+            self.errors.push((path, e));
+        }
+    }
+}
+
+impl<G: Game> Observer<G> for LpFileObserver {
+    fn lp_built(&mut self, player: usize, lp: &SparseModel, objective: &[(f64, usize)]) {
+        let path = |ext: &str| {
+            let mut name = self.prefix.clone().into_os_string();
+            name.push(format!("_p{player}.{ext}"));
+            PathBuf::from(name)
+        };
+        let (lp_path, mps_path) = (path("lp"), path("mps"));
+        self.write(lp_path, |w| lp.write_lp(w, objective));
+        self.write(mps_path, |w| lp.write_mps(w, objective));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn lp_file_errors_are_kept() {
+        let mut obs = LpFileObserver::new("/nonexistent/dir/rps");
+        let tree = Tree::new_observed(RPS::new(), &mut obs);
+        assert!(tree.value().is_some());
+        // Both files for both players
+        assert_eq!(4, obs.errors.len());
+    }
+}
@@ -0,0 +1,188 @@
+use super::*;
+use serde::{de::{DeserializeOwned, Error}, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// How a Tree is stored: nodes in preorder, each naming its children by index, and the game snapshots they share
+// written once and named by index too
+#[derive(Serialize)]
+#[serde(bound = "G: Serialize, NodeType<G>: Serialize")]
+struct StoredRef<'a, G: Game> {
+    snapshots: Vec<(&'a G, &'a [usize])>,
+    nodes: Vec<NodeRef<'a, G>>,
+}
+
+#[derive(Serialize)]
+#[serde(bound = "NodeType<G>: Serialize")]
+struct NodeRef<'a, G: Game> {
+    node_type: &'a NodeType<G>,
+    children: Vec<Option<usize>>,
+    snapshot: usize,
+    suffix: &'a [usize],
+    value: Option<f64>,
+    prob: Option<&'a [f64]>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "G: Deserialize<'de>, NodeType<G>: Deserialize<'de>")]
+struct Stored<G: Game> {
+    snapshots: Vec<(G, Vec<usize>)>,
+    nodes: Vec<StoredNode<G>>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "NodeType<G>: Deserialize<'de>")]
+struct StoredNode<G: Game> {
+    node_type: NodeType<G>,
+    children: Vec<Option<usize>>,
+    snapshot: usize,
+    suffix: Vec<usize>,
+    value: Option<f64>,
+    prob: Option<Vec<f64>>,
+}
+
+impl<'a, G: Game> StoredRef<'a, G> {
+    // Returns the index of node
+    fn add(&mut self, node: &'a Tree<G>, snapshot_ids: &mut HashMap<*const Snapshot<G>, usize>) -> usize {
+        let i = self.nodes.len();
+        let snapshot = *snapshot_ids.entry(Arc::as_ptr(&node.path.0)).or_insert_with(|| {
+            self.snapshots.push((&node.path.0.game, &node.path.0.path));
+            self.snapshots.len() - 1
+        });
+        self.nodes.push(NodeRef {
+            node_type: &node.node_type,
+            children: Vec::new(),
+            snapshot,
+            suffix: &node.path.1,
+            value: node.value,
+            prob: node.prob.as_deref(),
+        });
+        let children = node.children.iter().map(|c| c.as_ref().map(|c| self.add(c, snapshot_ids))).collect();
+        self.nodes[i].children = children;
+        i
+    }
+}
+
+impl<G: Game> Serialize for Tree<G> where G: Serialize, NodeType<G>: Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut stored = StoredRef { snapshots: Vec::new(), nodes: Vec::new() };
+        stored.add(self, &mut HashMap::new());
+        stored.serialize(serializer)
+    }
+}
+
+impl<'de, G: Game> Deserialize<'de> for Tree<G> where G: Deserialize<'de>, NodeType<G>: Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let stored = Stored::<G>::deserialize(deserializer)?;
+        let snapshots: Vec<Arc<Snapshot<G>>> = stored.snapshots.into_iter().map(|(game, path)| Arc::new(Snapshot { game, path })).collect();
+        // Built from the last node back, so children are done before their parent takes them
+        let mut built: Vec<Option<Tree<G>>> = std::iter::repeat_with(|| None).take(stored.nodes.len()).collect();
+        for (i, node) in stored.nodes.into_iter().enumerate().rev() {
+            let children = node.children.into_iter().map(|c| match c {
+                None => Ok(None),
+                Some(c) if c > i => built.get_mut(c).and_then(Option::take).map(Some).ok_or_else(|| D::Error::custom(format!("node {c} should exist and have one parent"))),
+                Some(c) => Err(D::Error::custom(format!("child {c} should come after its parent {i}"))),
+            }).collect::<Result<_, _>>()?;
+            let snapshot = snapshots.get(node.snapshot).cloned().ok_or_else(|| D::Error::custom(format!("snapshot {} should exist", node.snapshot)))?;
+            built[i] = Some(Tree { node_type: node.node_type, children, path: (snapshot, node.suffix), value: node.value, prob: node.prob });
+        }
+        built.into_iter().next().flatten().ok_or_else(|| D::Error::custom("tree should have a root"))
+    }
+}
+
+impl<G: Game> Tree<G> where Tree<G>: Serialize + DeserializeOwned {
+    pub fn save_bin<W: Write>(&self, w: W) -> bincode::Result<()> {
+        bincode::serialize_into(w, self)
+    }
+
+    pub fn load_bin<R: Read>(r: R) -> bincode::Result<Tree<G>> {
+        bincode::deserialize_from(r)
+    }
+
+    pub fn save_json<W: Write>(&self, w: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(w, self)
+    }
+
+    pub fn load_json<R: Read>(r: R) -> serde_json::Result<Tree<G>> {
+        serde_json::from_reader(r)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        if is_json(path) {
+            self.save_json(&mut w)?;
+        } else {
+            self.save_bin(&mut w).map_err(io::Error::other)?;
+        }
+        w.flush()
+    }
+
+    pub fn load_from_file(path: &Path) -> io::Result<Tree<G>> {
+        let r = BufReader::new(File::open(path)?);
+        if is_json(path) {
+            Ok(Tree::load_json(r)?)
+        } else {
+            Tree::load_bin(r).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+}
+
+impl<G: Game + Clone + Debug + PartialEq> Tree<G> where Tree<G>: Serialize + DeserializeOwned {
+    // Loads the solved tree at path, or builds, solves and stores it there if it doesn't exist yet or was built from a
+    // different starting state than g
+    pub fn cached(g: G, path: &Path) -> io::Result<Tree<G>> {
+        if path.exists() {
+            let tree = Tree::load_from_file(path)?;
+            if tree.path.0.path.is_empty() && tree.path.0.game == g {
+                return Ok(tree);
+            }
+        }
+        let tree = Tree::new(g);
+        tree.save_to_file(path)?;
+        Ok(tree)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn round_trip_shares_snapshots() {
+        let tree = Tree::new(RPS::new());
+        let mut bin = Vec::new();
+        tree.save_bin(&mut bin).unwrap();
+        let loaded: Tree<RPS> = Tree::load_bin(&bin[..]).unwrap();
+        let mut json = Vec::new();
+        loaded.save_json(&mut json).unwrap();
+        let loaded: Tree<RPS> = Tree::load_json(&json[..]).unwrap();
+        assert_eq!(tree.value(), loaded.value());
+        assert_eq!(tree.prob(), loaded.prob());
+        // RPS is shorter than a snapshot interval, so every node shares the root's
+        let p2 = loaded.child(0).unwrap();
+        assert!(Arc::ptr_eq(&loaded.path.0, &p2.path.0));
+        assert!(Arc::ptr_eq(&loaded.path.0, &p2.child(2).unwrap().path.0));
+        assert_eq!(p2.child(2).unwrap().value(), tree.child(0).unwrap().child(2).unwrap().value());
+    }
+
+    #[test]
+    fn cached_rebuilds_a_different_game() {
+        let path = std::env::temp_dir().join(format!("cached_rebuilds_{}.json", std::process::id()));
+        let tree = Tree::cached(RPS::new(), &path).unwrap();
+        let stored = std::fs::read_to_string(&path).unwrap();
+        // The same file, as if it had been built after P1 chose
+        std::fs::write(&path, stored.replace("\"p1_choice\": null", "\"p1_choice\": 1")).unwrap();
+        assert_ne!(RPS::new(), Tree::<RPS>::load_from_file(&path).unwrap().path.0.game);
+        let rebuilt = Tree::cached(RPS::new(), &path).unwrap();
+        assert_eq!(tree.value(), rebuilt.value());
+        assert_eq!(RPS::new(), Tree::<RPS>::load_from_file(&path).unwrap().path.0.game);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use super::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+// Action probabilities for every information set of one player, keyed by the InfoSet's Display
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PolicyTable {
+    pub entries: BTreeMap<String, Vec<(String, f64)>>,
+}
+
+pub fn policy_tables<G: Game>(tree: &Tree<G>) -> (PolicyTable, PolicyTable) {
+    let mut tables = (PolicyTable::default(), PolicyTable::default());
+    collect_rec(tree, &Histories::default(), &mut tables);
+    tables
+}
+
+fn collect_rec<G: Game>(node: &Tree<G>, hist: &Histories, tables: &mut (PolicyTable, PolicyTable)) {
+    if let (Some(info_set), Some(prob)) = (hist.info_set(&node.node_type), &node.prob) {
+        let table = if info_set.player == 1 { &mut tables.0 } else { &mut tables.1 };
+        table.entries.entry(info_set.to_string()).or_insert_with(||
+            info_set.choices.iter().cloned().zip(prob.iter().cloned()).collect()
+        );
+    }
+    for i in 0..node.children.len() {
+        if let Some(child) = &node.children[i] {
+            collect_rec(child, &hist.child(&node.node_type, i), tables);
+        }
+    }
+}
+
+impl PolicyTable {
+    pub fn get(&self, info_set: &InfoSet) -> Option<&Vec<(String, f64)>> {
+        self.entries.get(&info_set.to_string())
+    }
+
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "info_set,action,probability")?;
+        for (info_set, actions) in &self.entries {
+            for (action, p) in actions {
+                writeln!(w, "{},{},{}", csv_field(info_set), csv_field(action), p)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_csv<R: Read>(mut r: R) -> io::Result<PolicyTable> {
+        let mut buf = String::new();
+        r.read_to_string(&mut buf)?;
+        let mut table = PolicyTable::default();
+        for record in csv_records(&buf).into_iter().skip(1) {
+            let [info_set, action, p] = <[String; 3]>::try_from(record)
+                .map_err(|r| io::Error::new(io::ErrorKind::InvalidData, format!("policy rows should have 3 fields: {r:?}")))?;
+            let p = p.parse::<f64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            table.entries.entry(info_set).or_default().push((action, p));
+        }
+        Ok(table)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn write_json<W: Write>(&self, w: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(w, self)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn read_json<R: Read>(r: R) -> serde_json::Result<PolicyTable> {
+        serde_json::from_reader(r)
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn csv_records(s: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+// Plays by sampling from a policy table, tracking its own history to find the current information set
+pub struct PolicyPlayer {
+    table: PolicyTable,
+    player: usize,
+    history: Vec<Observation>,
+}
+
+impl PolicyPlayer {
+    pub fn new(table: PolicyTable, player: usize) -> PolicyPlayer {
+        PolicyPlayer { table, player, history: Vec::new() }
+    }
+}
+
+impl<T: PlayerTraits> Player<T> for PolicyPlayer {
+    fn receive_message(&mut self, msg: &T::Message) {
+        self.history.push(Observation::Message(msg.to_string()));
+    }
+
+    fn choose(&mut self, v: &Vec<T::Choice>) -> usize {
+        let info_set = InfoSet::new(self.player, &self.history, v);
+        let weights: Vec<f64> = match self.table.get(&info_set) {
+            Some(actions) => info_set.choices.iter().map(|c|
+                actions.iter().find(|(a, _)| a == c).map_or(0., |(_, p)| p.max(0.))
+            ).collect(),
+            None => vec![1.; v.len()],
+        };
+        let choice = WeightedIndex::new(&weights)
+            .or_else(|_| WeightedIndex::new(vec![1.; v.len()]))
+            .expect("choice list should not be empty")
+            .sample(&mut rand::thread_rng());
+        self.history.push(Observation::Choice(v[choice].to_string()));
+        choice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn tables_round_trip() {
+        let tree = Tree::new(RPS::new());
+        let (p1, p2) = policy_tables(&tree);
+        assert_eq!(1, p1.entries.len());
+        assert_eq!(1, p2.entries.len());
+        for (_, p) in p1.entries.values().flatten() {
+            assert!((p - 1./3.).abs() < 1e-6);
+        }
+        // Information sets display their choice lists with commas, so the CSV has to quote them
+        let mut csv = Vec::new();
+        p1.write_csv(&mut csv).unwrap();
+        assert_eq!(p1, PolicyTable::read_csv(&csv[..]).unwrap());
+        #[cfg(feature = "serde")]
+        {
+            let mut json = Vec::new();
+            p2.write_json(&mut json).unwrap();
+            assert_eq!(p2, PolicyTable::read_json(&json[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn csv_fields_keep_quotes_and_newlines() {
+        let mut table = PolicyTable::default();
+        table.entries.insert("said \"hi\",\nthen".to_string(), vec![("a,b".to_string(), 0.25), ("c".to_string(), 0.75)]);
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        assert_eq!(table, PolicyTable::read_csv(&csv[..]).unwrap());
+    }
+}
@@ -0,0 +1,258 @@
+use super::*;
+use super::abstraction::Abstraction;
+use std::collections::HashMap;
+
+// Information sets key on how everything a player observed displays, so a player who was told different things, or
+// chose differently, forgets it when the two display the same. Recall also breaks when exact's key, the messages so
+// far and the number of choices, joins nodes that offer different choices
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecallViolation {
+    // One path per distinct sequence of messages and choices the player actually observed
+    Forgot { info_set: InfoSet, paths: Vec<Vec<usize>> },
+    // One path per distinct choice list that exact::solve would play with one strategy
+    ChoicesDiffer { info_set: InfoSet, paths: Vec<Vec<usize>> },
+}
+
+impl Display for RecallViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (info_set, paths, what) = match self {
+            RecallViolation::Forgot { info_set, paths } => (info_set, paths, "different observations"),
+            RecallViolation::ChoicesDiffer { info_set, paths } => (info_set, paths, "different choice lists"),
+        };
+        write!(f, "{info_set} is reached with {what} at")?;
+        for p in paths {
+            write!(f, " {p:?}")?;
+        }
+        Ok(())
+    }
+}
+
+// Distinct values under a key, each with the first path it was seen at
+type Seen<V> = Vec<(V, Vec<usize>)>;
+
+fn see<V: PartialEq>(seen: &mut Seen<V>, value: V, path: &[usize]) {
+    if !seen.iter().any(|(v, _)| *v == value) {
+        seen.push((value, path.to_vec()));
+    }
+}
+
+fn intern<K: Eq + Hash>(ids: &mut HashMap<K, usize>, key: K) -> usize {
+    let next = ids.len();
+    *ids.entry(key).or_insert(next)
+}
+
+// What a player observed, compared as the values themselves rather than how they display
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Observed<M, C> {
+    Message(M),
+    Choice(C),
+}
+
+// Everything one player has seen on the way down. seq identifies the player's sequence the way exact's PlayerTree
+// does, and observed holds what history displays
+struct Walk<B, M, C> {
+    seq: usize,
+    msgs: Vec<M>,
+    history: Vec<Observation>,
+    observed: Vec<Observed<M, C>>,
+    seqs: HashMap<(usize, B, usize, usize), usize>,
+}
+
+impl<B: Eq + Hash, M, C> Walk<B, M, C> {
+    fn new() -> Walk<B, M, C> {
+        Walk { seq: usize::MAX, msgs: Vec::new(), history: Vec::new(), observed: Vec::new(), seqs: HashMap::new() }
+    }
+}
+
+type Observed1<G> = Vec<Observed<<<G as Game>::P1 as PlayerTraits>::Message, <<G as Game>::P1 as PlayerTraits>::Choice>>;
+type Observed2<G> = Vec<Observed<<<G as Game>::P2 as PlayerTraits>::Message, <<G as Game>::P2 as PlayerTraits>::Choice>>;
+
+// Choice lists under exact's key: the sequence, the bucket of the messages so far and the number of choices
+type ChoiceLists<B, C> = HashMap<(usize, B, usize), Seen<Vec<C>>>;
+
+struct Found<G: Game, A: Abstraction<G>> {
+    // The distinct observations behind each information set, by player
+    observed1: HashMap<InfoSet, Seen<Observed1<G>>>,
+    observed2: HashMap<InfoSet, Seen<Observed2<G>>>,
+    choices1: ChoiceLists<A::Bucket1, <G::P1 as PlayerTraits>::Choice>,
+    choices2: ChoiceLists<A::Bucket2, <G::P2 as PlayerTraits>::Choice>,
+}
+
+// Perfect recall holds when the nodes of every information set share everything their player observed.
+// Pass NoAbstraction unless the tree is solved with one, whose buckets then decide what exact joins
+pub fn check_recall<G: Game, T: GameTree<G>, A: Abstraction<G>>(tree: &T, abstraction: &A) -> Vec<RecallViolation> {
+    let mut found = Found::<G, A> { observed1: HashMap::new(), observed2: HashMap::new(), choices1: HashMap::new(), choices2: HashMap::new() };
+    check_rec(tree, tree.root(), &mut Vec::new(), &mut (Walk::new(), Walk::new()), abstraction, &mut found);
+    let info_set = |path: &[usize]| {
+        let mut hist = Histories::default();
+        let mut node = tree.root();
+        for &i in path {
+            hist = hist.child(tree.node_type(node), i);
+            node = tree.child(node, i).expect("recorded paths should be expanded");
+        }
+        hist.info_set(tree.node_type(node)).expect("recorded paths should end at a decision")
+    };
+    let forgot1 = found.observed1.into_iter().map(|(i, seen)| (i, seen.into_iter().map(|(_, p)| p).collect::<Vec<_>>()));
+    let forgot2 = found.observed2.into_iter().map(|(i, seen)| (i, seen.into_iter().map(|(_, p)| p).collect::<Vec<_>>()));
+    let mut ret: Vec<RecallViolation> = forgot1.chain(forgot2).filter(|(_, p)| p.len() > 1)
+        .map(|(info_set, paths)| RecallViolation::Forgot { info_set, paths }).collect();
+    let differ = found.choices1.into_values().map(|v| v.into_iter().map(|(_, p)| p).collect::<Vec<_>>())
+        .chain(found.choices2.into_values().map(|v| v.into_iter().map(|(_, p)| p).collect()));
+    for paths in differ.filter(|p| p.len() > 1) {
+        ret.push(RecallViolation::ChoicesDiffer { info_set: info_set(&paths[0]), paths });
+    }
+    ret.sort_by_key(|v| v.to_string());
+    ret
+}
+
+// Panics listing every violation, as solving such a tree gives strategies that cannot be played
+pub fn assert_perfect_recall<G: Game, T: GameTree<G>, A: Abstraction<G>>(tree: &T, abstraction: &A) {
+    let violations = check_recall(tree, abstraction);
+    if !violations.is_empty() {
+        let lines: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        panic!("game should have perfect recall, but:\n{}", lines.join("\n"));
+    }
+}
+
+type Walks<G, A> = (
+    Walk<<A as Abstraction<G>>::Bucket1, <<G as Game>::P1 as PlayerTraits>::Message, <<G as Game>::P1 as PlayerTraits>::Choice>,
+    Walk<<A as Abstraction<G>>::Bucket2, <<G as Game>::P2 as PlayerTraits>::Message, <<G as Game>::P2 as PlayerTraits>::Choice>,
+);
+
+fn check_rec<'a, G: Game + 'a, T: GameTree<G>, A: Abstraction<G>>(tree: &'a T, node: T::Node<'a>, path: &mut Vec<usize>, walks: &mut Walks<G, A>, abstraction: &A, found: &mut Found<G, A>) {
+    let n = tree.num_children(node);
+    let rec = |i: usize, path: &mut Vec<usize>, walks: &mut Walks<G, A>, found: &mut Found<G, A>| {
+        if let Some(child) = tree.child(node, i) {
+            path.push(i);
+            check_rec(tree, child, path, walks, abstraction, found);
+            path.pop();
+        }
+    };
+    match tree.node_type(node) {
+        NodeType::Message1(m) => {
+            walks.0.msgs.push(m.clone());
+            walks.0.history.push(Observation::Message(m.to_string()));
+            walks.0.observed.push(Observed::Message(m.clone()));
+            rec(0, path, walks, found);
+            walks.0.msgs.pop();
+            walks.0.history.pop();
+            walks.0.observed.pop();
+        }
+        NodeType::Message2(m) => {
+            walks.1.msgs.push(m.clone());
+            walks.1.history.push(Observation::Message(m.to_string()));
+            walks.1.observed.push(Observed::Message(m.clone()));
+            rec(0, path, walks, found);
+            walks.1.msgs.pop();
+            walks.1.history.pop();
+            walks.1.observed.pop();
+        }
+        NodeType::Player1(c) => {
+            let w = &mut walks.0;
+            let bucket = abstraction.bucket1(&w.msgs);
+            see(found.observed1.entry(InfoSet::new(1, &w.history, c)).or_default(), w.observed.clone(), path);
+            see(found.choices1.entry((w.seq, bucket.clone(), n)).or_default(), c.clone(), path);
+            let saved = w.seq;
+            for (i, choice) in c.iter().enumerate() {
+                let w = &mut walks.0;
+                w.seq = intern(&mut w.seqs, (saved, bucket.clone(), n, i));
+                w.history.push(Observation::Choice(choice.to_string()));
+                w.observed.push(Observed::Choice(choice.clone()));
+                rec(i, path, walks, found);
+                walks.0.history.pop();
+                walks.0.observed.pop();
+            }
+            walks.0.seq = saved;
+        }
+        NodeType::Player2(c) => {
+            let w = &mut walks.1;
+            let bucket = abstraction.bucket2(&w.msgs);
+            see(found.observed2.entry(InfoSet::new(2, &w.history, c)).or_default(), w.observed.clone(), path);
+            see(found.choices2.entry((w.seq, bucket.clone(), n)).or_default(), c.clone(), path);
+            let saved = w.seq;
+            for (i, choice) in c.iter().enumerate() {
+                let w = &mut walks.1;
+                w.seq = intern(&mut w.seqs, (saved, bucket.clone(), n, i));
+                w.history.push(Observation::Choice(choice.to_string()));
+                w.observed.push(Observed::Choice(choice.clone()));
+                rec(i, path, walks, found);
+                walks.1.history.pop();
+                walks.1.observed.pop();
+            }
+            walks.1.seq = saved;
+        }
+        NodeType::Random(_) | NodeType::End => {
+            for i in 0..n {
+                rec(i, path, walks, found);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::abstraction::NoAbstraction;
+
+    // Displays only whether the card is high, so different low cards look the same
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct Card(i32);
+
+    impl Display for Card {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", if self.0 > 1 { "high" } else { "low" })
+        }
+    }
+
+    impl Serializable for Card {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = Card;
+        type Choice = Card;
+    }
+
+    // P1 is told one of three cards and bets on it being 0
+    #[derive(Clone, Debug, Default)]
+    struct Told {
+        card: Option<i32>,
+    }
+
+    impl Game for Told {
+        type P1 = Traits;
+        type P2 = Traits;
+        type RandomChoice = Card;
+        fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+            let cards: Vec<Card> = (0..3).map(Card).collect();
+            let card = *self.card.get_or_insert(g.random(&vec![1./3.; 3], &cards)? as i32);
+            g.p1_message(&Card(card))?;
+            let bet = g.p1_choice(&vec![Card(0), Card(2)])? == 1;
+            g.end(if bet && card == 0 { 1. } else { 0. });
+            None
+        }
+    }
+
+    #[test]
+    fn messages_that_display_the_same_are_forgotten() {
+        let mut tree = Tree::new_root(Told::default());
+        expand_full(&mut tree);
+        let violations = check_recall(&tree, &NoAbstraction);
+        assert_eq!(1, violations.len());
+        match &violations[0] {
+            RecallViolation::Forgot { info_set, paths } => {
+                assert_eq!(1, info_set.player);
+                assert_eq!(vec![vec![0, 0], vec![1, 0]], *paths);
+            }
+            v => panic!("expected P1 to forget the card, got {v}"),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::common::lp_solver::{DefaultSolver, Solver};
+
+use super::abstraction::NoAbstraction;
+use super::exact::{add_leaf_constraints, Exploration, LeafEvaluator, NoLeaves, PlayerTree};
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+// A player's sequence: the (messages so far, choice count) key of each of their information sets on the way, and the choice taken
+type Sequence<M> = Vec<(Vec<M>, usize, usize)>;
+// A player's information set at a subgame root: their sequence and every message they got
+type InfoSetKey<M> = (Sequence<M>, Vec<M>);
+// The f64 is the opponent's opt-out value at that information set
+type Roots<M> = HashMap<InfoSetKey<M>, (PlayerTree<DefaultSolver, Vec<M>>, f64)>;
+
+// Where a subgame root sits in both players' sequence forms, read off the trunk solution along its path
+struct Entry<G: Game> {
+    seq1: Sequence<<G::P1 as PlayerTraits>::Message>,
+    seq2: Sequence<<G::P2 as PlayerTraits>::Message>,
+    msgs1: Vec<<G::P1 as PlayerTraits>::Message>,
+    msgs2: Vec<<G::P2 as PlayerTraits>::Message>,
+    nature: f64,
+    reach1: f64,
+    reach2: f64,
+}
+
+impl<G: Game> Entry<G> {
+    fn key1(&self) -> InfoSetKey<<G::P1 as PlayerTraits>::Message> {
+        (self.seq1.clone(), self.msgs1.clone())
+    }
+
+    fn key2(&self) -> InfoSetKey<<G::P2 as PlayerTraits>::Message> {
+        (self.seq2.clone(), self.msgs2.clone())
+    }
+}
+
+fn entry<G: Game + Clone, T: GameTree<G>>(tree: &T, path: &[usize]) -> Entry<G> {
+    let mut e = Entry { seq1: Vec::new(), seq2: Vec::new(), msgs1: Vec::new(), msgs2: Vec::new(), nature: 1., reach1: 1., reach2: 1. };
+    let mut node = tree.root();
+    for &i in path {
+        match tree.node_type(node) {
+            NodeType::Message1(m) => e.msgs1.push(m.clone()),
+            NodeType::Message2(m) => e.msgs2.push(m.clone()),
+            NodeType::Player1(c) => {
+                e.seq1.push((e.msgs1.clone(), c.len(), i));
+                e.reach1 *= tree.prob(node).expect("trunk should be solved")[i];
+            }
+            NodeType::Player2(c) => {
+                e.seq2.push((e.msgs2.clone(), c.len(), i));
+                e.reach2 *= tree.prob(node).expect("trunk should be solved")[i];
+            }
+            NodeType::Random(_) => e.nature *= tree.prob(node).expect("random nodes should have prob")[i],
+            NodeType::End => panic!("subgame roots should not be below an end node"),
+        }
+        node = tree.child(node, i).expect("subgame roots should be expanded");
+    }
+    e
+}
+
+// Pins the resolving player's sequence weights below the roots to the trunk strategy, down to the leaves
+struct Pin<'a, G: Game, T: GameTree<G>> {
+    lp: &'a mut DefaultSolver,
+    tree: &'a T,
+    pinned: HashSet<usize>,
+    game_type: PhantomData<G>,
+}
+
+impl<'a, G: Game + Clone + 'a, T: GameTree<G>> Pin<'a, G, T> {
+    fn pin<K: Eq + Hash>(&mut self, parent: &PlayerTree<DefaultSolver, K>, children: &[PlayerTree<DefaultSolver, K>], prob: &[f64]) {
+        // Normalized, so the pinned children sum to their parent exactly
+        let total: f64 = prob.iter().sum();
+        for (child, p) in children.iter().zip(prob) {
+            if self.pinned.insert(child.strategy) {
+                self.lp.add_constraint(&vec![(1., child.strategy), (-p/total, parent.strategy)], Ordering::Equal, 0.);
+            }
+        }
+    }
+
+    // Only the resolving player's tree is given
+    fn rec(&mut self, p1: Option<&PlayerTree<DefaultSolver, Vec<<G::P1 as PlayerTraits>::Message>>>, p2: Option<&PlayerTree<DefaultSolver, Vec<<G::P2 as PlayerTraits>::Message>>>, node: T::Node<'a>, msgs1: &[<G::P1 as PlayerTraits>::Message], msgs2: &[<G::P2 as PlayerTraits>::Message]) {
+        let tree = self.tree;
+        // Leaves have no sequences below them to pin
+        let children = (0..tree.num_children(node)).filter_map(|i| Some((i, tree.child(node, i)?)));
+        match tree.node_type(node) {
+            NodeType::Message1(m) => children.for_each(|(_, c)| self.rec(p1, p2, c, &[msgs1, std::slice::from_ref(m)].concat(), msgs2)),
+            NodeType::Message2(m) => children.for_each(|(_, c)| self.rec(p1, p2, c, msgs1, &[msgs2, std::slice::from_ref(m)].concat())),
+            NodeType::Player1(c) => {
+                let seqs = p1.map(|p| &p.children.get(&(msgs1.to_vec(), c.len())).expect("subgame should be fully explored").0);
+                if let (Some(p), Some(seqs)) = (p1, seqs) {
+                    self.pin(p, seqs, tree.prob(node).expect("trunk should be solved"));
+                }
+                children.for_each(|(i, c)| self.rec(seqs.map(|s| &s[i]), p2, c, msgs1, msgs2));
+            }
+            NodeType::Player2(c) => {
+                let seqs = p2.map(|p| &p.children.get(&(msgs2.to_vec(), c.len())).expect("subgame should be fully explored").0);
+                if let (Some(p), Some(seqs)) = (p2, seqs) {
+                    self.pin(p, seqs, tree.prob(node).expect("trunk should be solved"));
+                }
+                children.for_each(|(i, c)| self.rec(p1, seqs.map(|s| &s[i]), c, msgs1, msgs2));
+            }
+            NodeType::Random(_) | NodeType::End => children.for_each(|(_, c)| self.rec(p1, p2, c, msgs1, msgs2)),
+        }
+    }
+}
+
+// With the resolving player pinned to the trunk, each opponent root's value is the opponent's counterfactual
+// best-response value at that information set, which becomes its opt-out value
+fn opt_out_values<M: Eq + Hash>(lp: &mut DefaultSolver, opponents: &mut Roots<M>, direction: f64) {
+    let goal = opponents.values().map(|(p, _)| (direction, p.weighted_value)).collect();
+    // Drops the pinning rows along with the payoff rows, so the resolve is free below the roots
+    let sol = lp.solve_consuming(goal);
+    for (p, opt_out) in opponents.values_mut() {
+        *opt_out = sol(p.weighted_value);
+    }
+}
+
+// At each of its information sets at the roots, the opponent either enters the subgame or opts out for its value
+// against the trunk strategy, whichever is better for it; direction is 1 when P1 resolves and -1 when P2 does
+fn add_gadget<M: Eq + Hash>(lp: &mut DefaultSolver, opponents: &Roots<M>, direction: f64) -> Vec<(f64, usize)> {
+    let mut goal = Vec::new();
+    for (p, opt_out) in opponents.values() {
+        let g = lp.new_var();
+        lp.add_constraint(&vec![(direction, g), (-direction, p.weighted_value)], Ordering::Less, 0.);
+        lp.add_constraint(&vec![(direction, g)], Ordering::Less, direction * *opt_out);
+        goal.push((direction, g));
+    }
+    goal
+}
+
+// Re-solves player's strategy below the given roots, keeping the trunk strategy everywhere else. Roots the player
+// cannot tell apart should be resolved together, as they share information sets. With the gadget, the new strategy
+// does no worse against a best response than the trunk strategy did (Burch, Johanson and Bowling 2014)
+pub fn resolve<G: Game + Clone, T: GameTree<G>>(tree: &mut T, roots: &[Vec<usize>], player: usize) {
+    resolve_with_leaves(tree, roots, player, &NoLeaves)
+}
+
+// Like resolve, for subgames cut off where leaves gives their value, as when the trunk was solved with the same leaves.
+// The ancestors of the roots keep their strategy, and their values are recomputed from the subgames
+pub fn resolve_with_leaves<G: Game + Clone, T: GameTree<G>>(tree: &mut T, roots: &[Vec<usize>], player: usize, leaves: &dyn LeafEvaluator) {
+    let mut s1 = DefaultSolver::new();
+    let mut s2 = DefaultSolver::new();
+    let mut p1s: Roots<<G::P1 as PlayerTraits>::Message> = HashMap::new();
+    let mut p2s: Roots<<G::P2 as PlayerTraits>::Message> = HashMap::new();
+    let entries: Vec<Entry<G>> = roots.iter().map(|path| entry(&*tree, path)).collect();
+    for (path, e) in roots.iter().zip(&entries) {
+        let node = tree.find(path).expect("subgame roots should be expanded");
+        // Counterfactual for the opponent: their own reach is left out
+        let weight = e.nature * if player == 1 { e.reach1 } else { e.reach2 };
+        let p1 = &mut p1s.entry(e.key1()).or_insert_with(|| (PlayerTree::new(&mut s1, &mut s2), 0.)).0;
+        let p2 = &mut p2s.entry(e.key2()).or_insert_with(|| (PlayerTree::new(&mut s2, &mut s1), 0.)).0;
+        Exploration::new(&mut s1, &mut s2, &*tree, path.clone(), leaves, &NoAbstraction).rec(p1, p2, Some(node), weight, &e.msgs1, &e.msgs2);
+    }
+    let solution = if player == 1 {
+        for (p, _) in p1s.values() {
+            s1.add_constraint(&vec![(1., p.strategy)], Ordering::Equal, 1.);
+        }
+        s1.keep();
+        for (p, _) in p2s.values_mut() {
+            add_leaf_constraints(&mut s1, p);
+        }
+        let mut pin = Pin { lp: &mut s1, tree: &*tree, pinned: HashSet::new(), game_type: PhantomData };
+        for (path, e) in roots.iter().zip(&entries) {
+            pin.rec(Some(&p1s[&e.key1()].0), None, tree.find(path).expect("subgame roots should be expanded"), &e.msgs1, &e.msgs2);
+        }
+        opt_out_values(&mut s1, &mut p2s, 1.);
+        // Only the payoff rows come back
+        for (p, _) in p2s.values_mut() {
+            add_leaf_constraints(&mut s1, p);
+        }
+        let goal = add_gadget(&mut s1, &p2s, 1.);
+        let mut resolution = Resolution { tree: &*tree, sol: s1.solve_consuming(goal), leaves, path: Vec::new(), solution: Vec::new(), game_type: PhantomData };
+        for (path, e) in roots.iter().zip(&entries) {
+            let p1 = &p1s[&e.key1()].0;
+            resolution.path = path.clone();
+            resolution.rec(Some(p1), None, tree.find(path).expect("subgame roots should be expanded"), &e.msgs1, &e.msgs2);
+        }
+        resolution.solution
+    } else {
+        for (p, _) in p2s.values() {
+            s2.add_constraint(&vec![(1., p.strategy)], Ordering::Equal, 1.);
+        }
+        s2.keep();
+        for (p, _) in p1s.values_mut() {
+            add_leaf_constraints(&mut s2, p);
+        }
+        let mut pin = Pin { lp: &mut s2, tree: &*tree, pinned: HashSet::new(), game_type: PhantomData };
+        for (path, e) in roots.iter().zip(&entries) {
+            pin.rec(None, Some(&p2s[&e.key2()].0), tree.find(path).expect("subgame roots should be expanded"), &e.msgs1, &e.msgs2);
+        }
+        opt_out_values(&mut s2, &mut p1s, -1.);
+        // Only the payoff rows come back
+        for (p, _) in p1s.values_mut() {
+            add_leaf_constraints(&mut s2, p);
+        }
+        let goal = add_gadget(&mut s2, &p1s, -1.);
+        let mut resolution = Resolution { tree: &*tree, sol: s2.solve_consuming(goal), leaves, path: Vec::new(), solution: Vec::new(), game_type: PhantomData };
+        for (path, e) in roots.iter().zip(&entries) {
+            let p2 = &p2s[&e.key2()].0;
+            resolution.path = path.clone();
+            resolution.rec(None, Some(p2), tree.find(path).expect("subgame roots should be expanded"), &e.msgs1, &e.msgs2);
+        }
+        resolution.solution
+    };
+    for (path, prob, value) in solution {
+        tree.set_solution(&path, prob, value);
+    }
+    // Deepest first, so every ancestor sees its children's new values
+    let mut ancestors: Vec<Vec<usize>> = roots.iter().flat_map(|path| (0..path.len()).map(|n| path[..n].to_vec()))
+        .collect::<HashSet<_>>().into_iter().collect();
+    ancestors.sort_by_key(|path| std::cmp::Reverse(path.len()));
+    for mut path in ancestors {
+        let node = tree.find(&path).expect("ancestors of subgame roots should be expanded");
+        let prob = tree.prob(node).expect("trunk should be solved").to_vec();
+        let mut value = 0.;
+        for (i, p) in prob.iter().enumerate() {
+            path.push(i);
+            let child_value = match tree.child(node, i) {
+                Some(c) => tree.value(c),
+                None => leaves.leaf_value(&path),
+            };
+            path.pop();
+            value += child_value.map_or(0., |v| p*v);
+        }
+        tree.set_solution(&path, prob, value);
+    }
+}
+
+// Reads the resolved strategy off the LP solution sol, collecting every node's probabilities and value below the roots
+struct Resolution<'a, G: Game, T: GameTree<G>, F: Fn(usize) -> f64> {
+    tree: &'a T,
+    sol: F,
+    leaves: &'a dyn LeafEvaluator,
+    path: Vec<usize>,
+    solution: Vec<(Vec<usize>, Vec<f64>, f64)>,
+    game_type: PhantomData<G>,
+}
+
+impl<'a, G: Game + Clone + 'a, T: GameTree<G>, F: Fn(usize) -> f64> Resolution<'a, G, T, F> {
+    // Only the resolving player's tree is given; other nodes keep their trunk probabilities and get their values recomputed
+    fn rec(&mut self, p1: Option<&PlayerTree<DefaultSolver, Vec<<G::P1 as PlayerTraits>::Message>>>, p2: Option<&PlayerTree<DefaultSolver, Vec<<G::P2 as PlayerTraits>::Message>>>, node: T::Node<'a>, msgs1: &[<G::P1 as PlayerTraits>::Message], msgs2: &[<G::P2 as PlayerTraits>::Message]) -> f64 {
+        let (tree, sol) = (self.tree, &self.sol);
+        let n = tree.num_children(node);
+        let trunk = || tree.prob(node).expect("trunk should be solved").to_vec();
+        let mut next_msgs1 = msgs1.to_vec();
+        let mut next_msgs2 = msgs2.to_vec();
+        let mut next1 = vec![p1; n];
+        let mut next2 = vec![p2; n];
+        let prob = match tree.node_type(node) {
+            NodeType::Message1(m) => {
+                next_msgs1.push(m.clone());
+                vec![1.]
+            }
+            NodeType::Message2(m) => {
+                next_msgs2.push(m.clone());
+                vec![1.]
+            }
+            NodeType::Player1(c) => {
+                match p1 {
+                    Some(p) if sol(p.strategy) > EPS => {
+                        let children = &p.children.get(&(msgs1.to_vec(), c.len())).expect("subgame should be fully explored").0;
+                        next1 = children.iter().map(Some).collect();
+                        children.iter().map(|ch| sol(ch.strategy)/sol(p.strategy)).collect()
+                    }
+                    _ => {
+                        next1 = vec![None; n];
+                        trunk()
+                    }
+                }
+            }
+            NodeType::Player2(c) => {
+                match p2 {
+                    Some(p) if sol(p.strategy) > EPS => {
+                        let children = &p.children.get(&(msgs2.to_vec(), c.len())).expect("subgame should be fully explored").0;
+                        next2 = children.iter().map(Some).collect();
+                        children.iter().map(|ch| sol(ch.strategy)/sol(p.strategy)).collect()
+                    }
+                    _ => {
+                        next2 = vec![None; n];
+                        trunk()
+                    }
+                }
+            }
+            NodeType::Random(_) => trunk(),
+            NodeType::End => {
+                let value = tree.value(node).expect("end nodes should always have value");
+                self.solution.push((self.path.clone(), vec![], value));
+                return value;
+            }
+        };
+        let mut value = 0.;
+        for (i, (&p, (next1, next2))) in prob.iter().zip(next1.into_iter().zip(next2)).enumerate() {
+            self.path.push(i);
+            let child_value = match tree.child(node, i) {
+                Some(c) => Some(self.rec(next1, next2, c, &next_msgs1, &next_msgs2)),
+                None => self.leaves.leaf_value(&self.path),
+            };
+            self.path.pop();
+            if let Some(v) = child_value {
+                if p > EPS {
+                    value += v*p;
+                }
+            }
+        }
+        self.solution.push((self.path.clone(), prob, value));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    fn all_p2_nodes() -> Vec<Vec<usize>> {
+        (0..3).map(|i| vec![i]).collect()
+    }
+
+    fn p1_best_response<T: GameTree<RPS>>(tree: &T) -> f64 {
+        best_response(tree, &SequenceFormStrategy::from_tree(tree, 2)).1
+    }
+
+    #[test]
+    fn gadget_keeps_the_opponent_no_better_off() {
+        let mut tree = Tree::new(RPS::new());
+        // A trunk where P1 always plays paper against a P2 leaning on rock, values left stale
+        tree.set_solution(&[], vec![0., 1., 0.], 0.);
+        for path in all_p2_nodes() {
+            tree.set_solution(&path, vec![0.5, 0.25, 0.25], 0.);
+        }
+        let trunk = p1_best_response(&tree);
+        resolve(&mut tree, &all_p2_nodes(), 2);
+        assert!(p1_best_response(&tree) < trunk + 1e-6);
+        let (s1, s2) = (SequenceFormStrategy::from_tree(&tree, 1), SequenceFormStrategy::from_tree(&tree, 2));
+        assert!((tree.value().unwrap() - s1.expected_value(&s2, &tree)).abs() < 1e-6, "the root's value should follow the subgames");
+    }
+
+    #[test]
+    fn leaf_evaluated_subgames_resolve() {
+        let mut tree = Tree::new_root(RPS::new());
+        let mut leaves = HashMap::new();
+        for p1 in 0..3 {
+            expand(&mut tree, p1);
+            for p2 in 0..3 {
+                leaves.insert(vec![p1, p2], ((p1 + 4 - p2)%3) as f64 - 1.);
+            }
+        }
+        solve_with_leaves(&mut tree, &leaves);
+        resolve_with_leaves(&mut tree, &all_p2_nodes(), 2, &leaves);
+        for p1 in 0..3 {
+            for p in tree.child(p1).unwrap().prob().unwrap() {
+                assert!((p - 1./3.).abs() < 1e-6, "P2 should stay uniform");
+            }
+        }
+        assert!(tree.value().unwrap().abs() < 1e-6);
+    }
+}
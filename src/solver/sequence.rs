@@ -0,0 +1,181 @@
+use super::*;
+use std::collections::BTreeMap;
+
+// Realization weight of each of one player's sequences. A sequence is named by the information set (its Display) it
+// ends in and the index of the action taken there; the empty sequence always has weight 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceFormStrategy {
+    pub player: usize,
+    pub weights: BTreeMap<(String, usize), f64>,
+}
+
+impl SequenceFormStrategy {
+    pub fn new(player: usize) -> SequenceFormStrategy {
+        SequenceFormStrategy {
+            player,
+            weights: BTreeMap::new(),
+        }
+    }
+
+    // Reads the behavioral strategy stored in the tree's probabilities
+    pub fn from_tree<G: Game, T: GameTree<G>>(tree: &T, player: usize) -> SequenceFormStrategy {
+        let mut ret = SequenceFormStrategy::new(player);
+        from_tree_rec(tree, tree.root(), &Histories::default(), 1., &mut ret);
+        ret
+    }
+
+    pub fn weight(&self, info_set: &InfoSet, action: usize) -> f64 {
+        self.weights.get(&(info_set.to_string(), action)).cloned().unwrap_or(0.)
+    }
+
+    // Conditional action probabilities at an information set, uniform where the set is never reached
+    pub fn behavioral(&self, info_set: &InfoSet) -> Vec<f64> {
+        let n = info_set.choices.len();
+        let weights: Vec<f64> = (0..n).map(|i| self.weight(info_set, i)).collect();
+        let total: f64 = weights.iter().sum();
+        if total > EPS {
+            weights.iter().map(|w| w/total).collect()
+        } else {
+            vec_of_repeat(n, 1./n as f64)
+        }
+    }
+
+    // Plays self with probability lambda and other otherwise; realization weights mix linearly
+    pub fn mix(&self, other: &SequenceFormStrategy, lambda: f64) -> SequenceFormStrategy {
+        assert_eq!(self.player, other.player, "mixed strategies should belong to the same player");
+        let mut ret = SequenceFormStrategy::new(self.player);
+        for (k, w) in &self.weights {
+            *ret.weights.entry(k.clone()).or_insert(0.) += lambda*w;
+        }
+        for (k, w) in &other.weights {
+            *ret.weights.entry(k.clone()).or_insert(0.) += (1.-lambda)*w;
+        }
+        ret
+    }
+
+    // Value for self's player when playing against opponent, ignoring unexpanded branches
+    pub fn expected_value<G: Game, T: GameTree<G>>(&self, opponent: &SequenceFormStrategy, tree: &T) -> f64 {
+        assert_ne!(self.player, opponent.player, "opponent should be the other player");
+        let (s1, s2) = if self.player == 1 { (self, opponent) } else { (opponent, self) };
+        let value = value_rec(tree, tree.root(), &Histories::default(), (1., 1., 1.), s1, s2);
+        if self.player == 1 { value } else { -value }
+    }
+
+    // Writes the behavioral strategy into the tree's probabilities at this player's nodes, keeping the other
+    // probabilities and recomputing every value
+    pub fn apply<G: Game, T: GameTree<G>>(&self, tree: &mut T) {
+        let mut solution = Vec::new();
+        apply_rec(self, &*tree, Some(tree.root()), &Histories::default(), &mut Vec::new(), &mut solution);
+        for (path, prob, value) in solution {
+            tree.set_solution(&path, prob, value);
+        }
+    }
+}
+
+fn from_tree_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, hist: &Histories, weight: f64, ret: &mut SequenceFormStrategy) {
+    let node_type = tree.node_type(node);
+    let info_set = hist.info_set(node_type).filter(|i| i.player == ret.player);
+    for i in 0..tree.num_children(node) {
+        let mut child_weight = weight;
+        if let Some(info_set) = &info_set {
+            let prob = tree.prob(node).map_or(0., |p| p[i]);
+            child_weight *= prob;
+            ret.weights.entry((info_set.to_string(), i)).or_insert(child_weight);
+        }
+        if let Some(child) = tree.child(node, i) {
+            from_tree_rec(tree, child, &hist.child(node_type, i), child_weight, ret);
+        }
+    }
+}
+
+// Each player's weight is the realization weight of their sequence so far, so a leaf counts nature*w1*w2 of its value
+fn value_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, hist: &Histories, weights: (f64, f64, f64), s1: &SequenceFormStrategy, s2: &SequenceFormStrategy) -> f64 {
+    let (nature, w1, w2) = weights;
+    let node_type = tree.node_type(node);
+    if let NodeType::End = node_type {
+        return nature*w1*w2*tree.value(node).expect("end nodes should always have value");
+    }
+    let info_set = hist.info_set(node_type);
+    let mut value = 0.;
+    for i in 0..tree.num_children(node) {
+        let Some(child) = tree.child(node, i) else { continue };
+        let next = match node_type {
+            NodeType::Random(_) => (nature*tree.prob(node).expect("random nodes should have prob")[i], w1, w2),
+            NodeType::Player1(_) => (nature, s1.weight(info_set.as_ref().expect("player nodes should have an info set"), i), w2),
+            NodeType::Player2(_) => (nature, w1, s2.weight(info_set.as_ref().expect("player nodes should have an info set"), i)),
+            _ => weights,
+        };
+        if next.0*next.1*next.2 > 0. {
+            value += value_rec(tree, child, &hist.child(node_type, i), next, s1, s2);
+        }
+    }
+    value
+}
+
+fn apply_rec<'a, G: Game + 'a, T: GameTree<G>>(s: &SequenceFormStrategy, tree: &'a T, maybe_node: Option<T::Node<'a>>, hist: &Histories, path: &mut Vec<usize>, solution: &mut Vec<(Vec<usize>, Vec<f64>, f64)>) -> Option<f64> {
+    let node = maybe_node?;
+    let node_type = tree.node_type(node);
+    if let NodeType::End = node_type {
+        let value = tree.value(node).expect("end nodes should always have value");
+        solution.push((path.clone(), vec![], value));
+        return Some(value);
+    }
+    let n = tree.num_children(node);
+    let prob = match hist.info_set(node_type) {
+        Some(info_set) if info_set.player == s.player => s.behavioral(&info_set),
+        _ => match tree.prob(node) {
+            Some(p) => p.to_vec(),
+            None if n == 1 => vec![1.],
+            None => vec_of_repeat(n, 1./n as f64),
+        },
+    };
+    let mut value = 0.;
+    for (i, &p) in prob.iter().enumerate() {
+        path.push(i);
+        let child_value = apply_rec(s, tree, tree.child(node, i), &hist.child(node_type, i), path, solution);
+        path.pop();
+        if let Some(v) = child_value {
+            if p > EPS {
+                value += v*p;
+            }
+        }
+    }
+    solution.push((path.clone(), prob, value));
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    // Puts all of the weight on one action at each of the player's information sets in the solved strategy
+    fn pure(solved: &SequenceFormStrategy, action: usize) -> SequenceFormStrategy {
+        let mut ret = SequenceFormStrategy::new(solved.player);
+        for (info_set, a) in solved.weights.keys() {
+            ret.weights.insert((info_set.clone(), *a), if *a == action { 1. } else { 0. });
+        }
+        ret
+    }
+
+    #[test]
+    fn strategies_round_trip_through_the_tree() {
+        let mut tree = Tree::new(RPS::new());
+        let (s1, s2) = (SequenceFormStrategy::from_tree(&tree, 1), SequenceFormStrategy::from_tree(&tree, 2));
+        assert_eq!(3, s1.weights.len());
+        assert!(s1.weights.values().chain(s2.weights.values()).all(|w| (w - 1./3.).abs() < 1e-6));
+        assert!(s1.expected_value(&s2, &tree).abs() < 1e-6);
+
+        // Paper beats rock
+        let (paper, rock) = (pure(&s1, 1), pure(&s2, 0));
+        assert!((paper.expected_value(&rock, &tree) - 1.).abs() < 1e-9);
+        assert!((rock.expected_value(&paper, &tree) + 1.).abs() < 1e-9);
+        assert!((paper.mix(&s1, 0.5).expected_value(&rock, &tree) - 0.5).abs() < 1e-6);
+
+        rock.apply(&mut tree);
+        paper.apply(&mut tree);
+        assert!((tree.value().expect("tree should have a value") - 1.).abs() < 1e-9);
+        assert_eq!(paper, SequenceFormStrategy::from_tree(&tree, 1));
+        assert_eq!(rock, SequenceFormStrategy::from_tree(&tree, 2));
+    }
+}
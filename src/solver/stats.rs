@@ -0,0 +1,228 @@
+use super::*;
+use crate::common::lp_solver::ModelStats;
+use rand::Rng;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeCounts {
+    pub message1: usize,
+    pub message2: usize,
+    pub player1: usize,
+    pub player2: usize,
+    pub random: usize,
+    pub end: usize,
+    // Children not expanded yet, which are not counted as nodes
+    pub unexpanded: usize,
+}
+
+impl NodeCounts {
+    pub fn total(&self) -> usize {
+        self.message1 + self.message2 + self.player1 + self.player2 + self.random + self.end
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeStats {
+    pub counts: NodeCounts,
+    // Expanded nodes at each depth, the root being at 0
+    pub depths: Vec<usize>,
+    // Decision and random nodes by their number of children
+    pub branching: BTreeMap<usize, usize>,
+    // Distinct information sets of P1 and P2
+    pub info_sets: (usize, usize),
+    // The LPs exact::solve would build, solved for P1's and P2's strategy
+    pub lp: (ModelStats, ModelStats),
+}
+
+impl TreeStats {
+    pub fn mean_branching(&self) -> f64 {
+        let nodes: usize = self.branching.values().sum();
+        let children: usize = self.branching.iter().map(|(b, n)| b*n).sum();
+        children as f64 / nodes.max(1) as f64
+    }
+}
+
+impl Display for TreeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let c = &self.counts;
+        writeln!(f, "{} nodes: {} P1, {} P2, {} random, {} end, {} P1 messages, {} P2 messages, {} unexpanded",
+            c.total(), c.player1, c.player2, c.random, c.end, c.message1, c.message2, c.unexpanded)?;
+        writeln!(f, "depth {}, nodes per depth {:?}", self.depths.len().saturating_sub(1), self.depths)?;
+        writeln!(f, "mean branching {:.2}, nodes per branching factor {:?}", self.mean_branching(), self.branching)?;
+        writeln!(f, "{} P1 and {} P2 information sets", self.info_sets.0, self.info_sets.1)?;
+        write!(f, "P1 LP: {}\nP2 LP: {}", self.lp.0, self.lp.1)
+    }
+}
+
+// Walks the expanded part of the tree, and builds exact's LPs over it without solving them
+pub fn tree_stats<G: Game + Clone, T: GameTree<G>>(tree: &T) -> TreeStats {
+    let mut stats = TreeStats {
+        counts: NodeCounts::default(),
+        depths: Vec::new(),
+        branching: BTreeMap::new(),
+        info_sets: (0, 0),
+        lp: Session::<G>::new().build(tree, &NoLeaves),
+    };
+    let mut info_sets = HashSet::new();
+    stats_rec(tree, tree.root(), 0, &Histories::default(), &mut info_sets, &mut stats);
+    stats.info_sets = (
+        info_sets.iter().filter(|i: &&InfoSet| i.player == 1).count(),
+        info_sets.iter().filter(|i: &&InfoSet| i.player == 2).count(),
+    );
+    stats
+}
+
+fn stats_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, depth: usize, hist: &Histories, info_sets: &mut HashSet<InfoSet>, stats: &mut TreeStats) {
+    let node_type = tree.node_type(node);
+    let n = tree.num_children(node);
+    let c = &mut stats.counts;
+    match node_type {
+        NodeType::Message1(_) => c.message1 += 1,
+        NodeType::Message2(_) => c.message2 += 1,
+        NodeType::Player1(_) => c.player1 += 1,
+        NodeType::Player2(_) => c.player2 += 1,
+        NodeType::Random(_) => c.random += 1,
+        NodeType::End => c.end += 1,
+    }
+    if let NodeType::Player1(_) | NodeType::Player2(_) | NodeType::Random(_) = node_type {
+        *stats.branching.entry(n).or_insert(0) += 1;
+    }
+    if stats.depths.len() <= depth {
+        stats.depths.push(0);
+    }
+    stats.depths[depth] += 1;
+    if let Some(info_set) = hist.info_set(node_type) {
+        info_sets.insert(info_set);
+    }
+    for i in 0..n {
+        match tree.child(node, i) {
+            Some(child) => stats_rec(tree, child, depth + 1, &hist.child(node_type, i), info_sets, stats),
+            None => stats.counts.unexpanded += 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SizeEstimate {
+    pub samples: usize,
+    pub nodes: f64,
+    // Standard error of nodes
+    pub std_error: f64,
+    pub ends: f64,
+    pub max_depth: usize,
+}
+
+impl Display for SizeEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "~{:.0} nodes (± {:.0}), ~{:.0} end nodes, deepest sample {}, from {} samples", self.nodes, self.std_error, self.ends, self.max_depth, self.samples)
+    }
+}
+
+// Knuth's estimator: a path of uniformly random children weighs each node on it by the product of the branching
+// factors above it, which is an unbiased estimate of the node count. Only the current node is kept, so games far too
+// large to expand can be measured
+pub fn estimate_size<G: Game + Clone + Debug, R: Rng>(game: &G, samples: usize, rng: &mut R) -> SizeEstimate {
+    let root = Arc::new(Snapshot { game: game.clone(), path: Vec::new() });
+    let mut totals = Vec::with_capacity(samples);
+    let mut ends = 0.;
+    let mut max_depth = 0;
+    for _ in 0..samples {
+        let mut node = explorer::make_node(root.clone(), vec![]);
+        let mut live = explorer::resume(&node.path);
+        let (mut weight, mut total, mut depth) = (1., 0., 0);
+        loop {
+            total += weight;
+            let n = node.num_children();
+            if n == 0 {
+                break;
+            }
+            weight *= n as f64;
+            (node, live) = explorer::make_child(&node.path, &live, rng.gen_range(0..n));
+            depth += 1;
+        }
+        totals.push(total);
+        ends += weight;
+        max_depth = max_depth.max(depth);
+    }
+    let count = samples.max(1) as f64;
+    let nodes = totals.iter().sum::<f64>() / count;
+    let variance = totals.iter().map(|t| (t - nodes)*(t - nodes)).sum::<f64>() / (count - 1.).max(1.);
+    SizeEstimate { samples, nodes, std_error: (variance / count).sqrt(), ends: ends / count, max_depth }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+    use rand::SeedableRng;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct N(i32);
+
+    impl Display for N {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Serializable for N {
+        fn kind_sizes() -> Vec<usize> {
+            vec![1]
+        }
+        fn serialize(&self) -> (usize, Vec<i32>) {
+            (0, vec![self.0])
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Traits;
+
+    impl PlayerTraits for Traits {
+        type Message = N;
+        type Choice = N;
+    }
+
+    // P1 stops, or lets P2 pick one of three endings
+    #[derive(Clone, Debug, Default)]
+    struct Lopsided {
+        passed: bool,
+    }
+
+    impl Game for Lopsided {
+        type P1 = Traits;
+        type P2 = Traits;
+        type RandomChoice = N;
+        fn step(&mut self, g: &mut dyn GameInterface<Self>) -> Option<()> {
+            if !self.passed {
+                if g.p1_choice(&vec![N(0), N(1)])? == 0 {
+                    g.end(0.);
+                    return None;
+                }
+                self.passed = true;
+                return Some(());
+            }
+            let c = g.p2_choice(&vec![N(0), N(1), N(2)])?;
+            g.end(c as f64 - 1.);
+            None
+        }
+    }
+
+    #[test]
+    fn estimates_match_the_expanded_tree() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        // Every path of RPS has the same branching, so a single sample is exact
+        let stats = tree_stats(&Tree::new(RPS::new()));
+        let estimate = estimate_size(&RPS::new(), 1, &mut rng);
+        assert_eq!((13, 9), (stats.counts.total(), stats.counts.end));
+        assert_eq!((13., 9., 0.), (estimate.nodes, estimate.ends, estimate.std_error));
+
+        // Here one path estimates 3 nodes and the others 9
+        let stats = tree_stats(&Tree::new(Lopsided::default()));
+        let estimate = estimate_size(&Lopsided::default(), 2000, &mut rng);
+        assert_eq!((6, 4), (stats.counts.total(), stats.counts.end));
+        assert_eq!(vec![1, 2, 3], stats.depths);
+        assert!((estimate.nodes - 6.).abs() < 4.*estimate.std_error);
+        assert!((estimate.ends - 4.).abs() < 0.3);
+        assert_eq!(2, estimate.max_depth);
+    }
+}
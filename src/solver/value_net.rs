@@ -0,0 +1,236 @@
+use super::*;
+use crate::common::encoding::Encoder;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Layer {
+    inputs: usize,
+    // outputs x inputs, row major
+    weights: Vec<f64>,
+    bias: Vec<f64>,
+}
+
+// Fully connected network with ReLU hidden layers and a single linear output
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mlp {
+    layers: Vec<Layer>,
+}
+
+impl Mlp {
+    // sizes runs from the input width to the hidden widths, and should end in 1
+    pub fn new<R: Rng>(sizes: &[usize], rng: &mut R) -> Mlp {
+        let layers = sizes.windows(2).map(|w| {
+            let bound = (6. / w[0].max(1) as f64).sqrt();
+            Layer {
+                inputs: w[0],
+                weights: (0..w[0]*w[1]).map(|_| rng.gen_range(-bound..bound)).collect(),
+                bias: vec_of_repeat(w[1], 0.),
+            }
+        }).collect();
+        Mlp { layers }
+    }
+
+    // The input followed by every layer's output
+    fn activations(&self, x: &[f64]) -> Vec<Vec<f64>> {
+        let mut acts = vec![x.to_vec()];
+        for (l, layer) in self.layers.iter().enumerate() {
+            let input = &acts[l];
+            let out = layer.bias.iter().enumerate().map(|(o, b)| {
+                let z = b + layer.weights[o*layer.inputs..(o+1)*layer.inputs].iter().zip(input).map(|(w, a)| w*a).sum::<f64>();
+                if l + 1 < self.layers.len() { z.max(0.) } else { z }
+            }).collect();
+            acts.push(out);
+        }
+        acts
+    }
+
+    pub fn forward(&self, x: &[f64]) -> f64 {
+        self.activations(x).last().expect("networks should have an output")[0]
+    }
+
+    // One step of stochastic gradient descent on the squared error, returning the error before the step
+    pub fn train_step(&mut self, x: &[f64], target: f64, learning_rate: f64) -> f64 {
+        let acts = self.activations(x);
+        let err = acts[self.layers.len()][0] - target;
+        let mut delta = vec![err];
+        for l in (0..self.layers.len()).rev() {
+            let layer = &mut self.layers[l];
+            let mut prev = vec_of_repeat(layer.inputs, 0.);
+            for (o, d) in delta.iter().enumerate() {
+                for i in 0..layer.inputs {
+                    let w = &mut layer.weights[o*layer.inputs + i];
+                    prev[i] += *w * d;
+                    *w -= learning_rate * d * acts[l][i];
+                }
+                layer.bias[o] -= learning_rate * d;
+            }
+            for (p, a) in prev.iter_mut().zip(&acts[l]) {
+                if *a <= 0. {
+                    *p = 0.;
+                }
+            }
+            delta = prev;
+        }
+        err * err
+    }
+
+    // Shuffled passes over data, returning the mean squared error of the last one
+    pub fn train<R: Rng>(&mut self, data: &[(Vec<f64>, f64)], epochs: usize, learning_rate: f64, rng: &mut R) -> f64 {
+        let mut order: Vec<usize> = (0..data.len()).collect();
+        let mut loss = 0.;
+        for _ in 0..epochs {
+            order.shuffle(rng);
+            loss = order.iter().map(|&i| self.train_step(&data[i].0, data[i].1, learning_rate)).sum::<f64>() / data.len().max(1) as f64;
+        }
+        loss
+    }
+}
+
+// One player's observations: the messages they got and the choices they made
+struct Sources<P: PlayerTraits> {
+    messages: Encoder<P::Message>,
+    choices: Encoder<P::Choice>,
+}
+
+impl<P: PlayerTraits> Sources<P> {
+    fn new() -> Sources<P> {
+        Sources { messages: Encoder::new(), choices: Encoder::new() }
+    }
+
+    fn width(&self) -> usize {
+        self.messages.payload_width() + self.choices.payload_width()
+    }
+}
+
+// Which player acts, then P1's slots and P2's slots
+pub fn feature_width<G: Game>() -> usize {
+    2 + Sources::<G::P1>::new().width() + Sources::<G::P2>::new().width()
+}
+
+// The information state of the player who made the last choice or got the last message on path, or P1 when nobody
+// has: which player it is, and that player's messages and choices bagged into their slots, so paths of any length
+// share one width. The other player's slots and chance outcomes stay zero. The last index may point at an unexpanded
+// child
+pub fn path_features<G: Game, T: GameTree<G>>(tree: &T, path: &[usize]) -> Vec<f64> {
+    let (s1, s2) = (Sources::<G::P1>::new(), Sources::<G::P2>::new());
+    let mut bags = (vec_of_repeat(s1.width(), 0.), vec_of_repeat(s2.width(), 0.));
+    let split = (s1.messages.payload_width(), s2.messages.payload_width());
+    let mut acting = 1;
+    let mut node = Some(tree.root());
+    for &i in path {
+        let n = node.expect("only the last index of a path should be unexpanded");
+        match tree.node_type(n) {
+            NodeType::Message1(m) => {
+                acting = 1;
+                s1.messages.add_to_bag(m, &mut bags.0[..split.0])
+            }
+            NodeType::Message2(m) => {
+                acting = 2;
+                s2.messages.add_to_bag(m, &mut bags.1[..split.1])
+            }
+            NodeType::Player1(c) => {
+                acting = 1;
+                s1.choices.add_to_bag(&c[i], &mut bags.0[split.0..])
+            }
+            NodeType::Player2(c) => {
+                acting = 2;
+                s2.choices.add_to_bag(&c[i], &mut bags.1[split.1..])
+            }
+            NodeType::Random(_) => Ok(()),
+            NodeType::End => panic!("paths should not continue below an end node"),
+        }.expect("serialized observations should match kind_sizes");
+        node = tree.child(n, i);
+    }
+    let mut ret = vec_of_repeat(feature_width::<G>(), 0.);
+    ret[acting - 1] = 1.;
+    if acting == 1 {
+        ret[2..2 + bags.0.len()].copy_from_slice(&bags.0);
+    } else {
+        ret[2 + bags.0.len()..].copy_from_slice(&bags.1);
+    }
+    ret.into_iter().map(f64::from).collect()
+}
+
+// Estimates P1's value at a node from the information state of the player who acted last on the way to it
+#[derive(Clone, Debug)]
+pub struct ValueNet<G: Game> {
+    pub mlp: Mlp,
+    game_type: PhantomData<G>,
+}
+
+impl<G: Game> ValueNet<G> {
+    pub fn new<R: Rng>(hidden: &[usize], rng: &mut R) -> ValueNet<G> {
+        let mut sizes = vec![feature_width::<G>()];
+        sizes.extend_from_slice(hidden);
+        sizes.push(1);
+        ValueNet {
+            mlp: Mlp::new(&sizes, rng),
+            game_type: PhantomData,
+        }
+    }
+
+    pub fn estimate<T: GameTree<G>>(&self, tree: &T, path: &[usize]) -> f64 {
+        self.mlp.forward(&path_features(tree, path))
+    }
+
+    // Fits the values of every solved node, returning the final mean squared error
+    pub fn train<T: GameTree<G>, R: Rng>(&mut self, tree: &T, epochs: usize, learning_rate: f64, rng: &mut R) -> f64 {
+        let mut data = Vec::new();
+        collect_rec(tree, tree.root(), &mut Vec::new(), &mut data);
+        self.mlp.train(&data, epochs, learning_rate, rng)
+    }
+
+    // Like explorer::evaluate_frontier, ready to be passed to exact::solve_with_leaves
+    pub fn evaluate_frontier<T: GameTree<G>>(&self, tree: &T) -> HashMap<Vec<usize>, f64> {
+        let mut ret = HashMap::new();
+        frontier_rec(self, tree, tree.root(), &mut Vec::new(), &mut ret);
+        ret
+    }
+}
+
+fn collect_rec<'a, G: Game + 'a, T: GameTree<G>>(tree: &'a T, node: T::Node<'a>, path: &mut Vec<usize>, data: &mut Vec<(Vec<f64>, f64)>) {
+    if let Some(v) = tree.value(node) {
+        data.push((path_features(tree, path), v));
+    }
+    for i in 0..tree.num_children(node) {
+        if let Some(child) = tree.child(node, i) {
+            path.push(i);
+            collect_rec(tree, child, path, data);
+            path.pop();
+        }
+    }
+}
+
+fn frontier_rec<'a, G: Game + 'a, T: GameTree<G>>(net: &ValueNet<G>, tree: &'a T, node: T::Node<'a>, path: &mut Vec<usize>, ret: &mut HashMap<Vec<usize>, f64>) {
+    for i in 0..tree.num_children(node) {
+        path.push(i);
+        match tree.child(node, i) {
+            Some(child) => frontier_rec(net, tree, child, path, ret),
+            None => {
+                ret.insert(path.clone(), net.estimate(tree, path));
+            }
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rps::RPS;
+
+    #[test]
+    fn features_are_the_acting_players_information_state() {
+        let tree = Tree::new(RPS::new());
+        // P2 hasn't seen P1's choice, so it can't tell these apart
+        assert_eq!(path_features(&tree, &[0, 1]), path_features(&tree, &[2, 1]));
+        assert_ne!(path_features(&tree, &[0, 1]), path_features(&tree, &[0, 2]));
+        let p1 = path_features(&tree, &[0]);
+        assert_eq!((1., 0.), (p1[0], p1[1]));
+        assert_ne!(p1, path_features(&tree, &[1]));
+    }
+}